@@ -1,2 +1,408 @@
+use barista::access::AccessList;
+use barista::backup::BackupInfo;
+use barista::schedule::ScheduleInfo;
+use barista::server::ServerData;
+use barista::socket::SOCKET_PATH;
+use barista_client::{Client, ClientError};
+use chrono::{Local, TimeZone};
+use clap::{App, AppSettings, Arg};
+use log::error;
+use std::collections::BTreeMap;
+
+#[derive(Debug)]
+enum CliError {
+    ClientError(ClientError),
+    InvalidId(String),
+    InvalidProperty(String),
+    InvalidDelay(String),
+}
+
+impl From<ClientError> for CliError {
+    fn from(e: ClientError) -> Self {
+        Self::ClientError(e)
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Self::ClientError(e) => format!("{}", e),
+            Self::InvalidId(id) => format!("{} isn't a valid server id", id),
+            Self::InvalidProperty(p) => format!("{} isn't in the form KEY=VALUE", p),
+            Self::InvalidDelay(d) => format!("{} isn't a valid number of seconds", d),
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+fn print_server(server: &ServerData) {
+    let status = match server.stop_reason {
+        Some(reason) => format!("{} ({})", server.status, reason),
+        None => server.status.to_string(),
+    };
+    println!(
+        "{}: {} [{}] players: {}",
+        server.id, server.name, status, server.player_count
+    );
+
+    match (&server.minecraft_version, server.required_java) {
+        (Some(version), Some(java)) => println!("  minecraft {}, java {}+", version, java),
+        (Some(version), None) => println!("  minecraft {}", version),
+        (None, Some(java)) => println!("  java {}+", java),
+        (None, None) => {}
+    }
+}
+
+fn local_time(t: i64) -> String {
+    match Local.timestamp_opt(t, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => t.to_string(),
+    }
+}
+
+fn print_backup(backup: &BackupInfo) {
+    println!(
+        "{} {} bytes, made {}",
+        backup.name,
+        backup.size,
+        local_time(backup.created)
+    );
+}
+
+fn print_schedule(schedule: &ScheduleInfo) {
+    let state = if schedule.enabled { "" } else { " (disabled)" };
+    println!(
+        "{}: {} [{} {}]{}",
+        schedule.name, schedule.action, schedule.cron, schedule.timezone, state
+    );
+
+    if let Some(next) = schedule.next_run {
+        println!("  next run: {}", local_time(next));
+    }
+    if let Some(run) = &schedule.last_run {
+        match &run.error {
+            Some(e) => println!("  last run: {} failed: {}", local_time(run.time), e),
+            None => println!("  last run: {} ok", local_time(run.time)),
+        }
+    }
+}
+
+fn parse_id(matches: &clap::ArgMatches) -> Result<usize, CliError> {
+    let id = matches.value_of("id").unwrap();
+    id.parse().map_err(|_| CliError::InvalidId(id.to_string()))
+}
+
+fn parse_delay(matches: &clap::ArgMatches) -> Result<Option<u64>, CliError> {
+    match matches.value_of("delay") {
+        Some(delay) => delay
+            .parse()
+            .map(Some)
+            .map_err(|_| CliError::InvalidDelay(delay.to_string())),
+        None => Ok(None),
+    }
+}
+
+fn parse_list(name: &str) -> AccessList {
+    match name {
+        "whitelist" => AccessList::Whitelist,
+        "ops" => AccessList::Ops,
+        "bans" => AccessList::BannedPlayers,
+        "ip-bans" => AccessList::BannedIps,
+        _ => unreachable!(),
+    }
+}
+
+async fn access(client: &Client, matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let id = parse_id(matches)?;
+    let list = parse_list(matches.value_of("list").unwrap());
+
+    let entries = match (matches.value_of("action"), matches.value_of("name")) {
+        (Some("add"), Some(name)) => client.add_access(id, list, name).await?,
+        (Some("remove"), Some(name)) => client.remove_access(id, list, name).await?,
+        _ => client.access_list(id, list).await?,
+    };
+
+    for entry in entries.iter() {
+        match &entry.uuid {
+            Some(uuid) => println!("{} {}", entry.name, uuid),
+            None => println!("{}", entry.name),
+        }
+    }
+
+    Ok(())
+}
+
+// lists every property, or sets the ones given as KEY=VALUE
+async fn properties(client: &Client, matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let id = parse_id(matches)?;
+
+    let props = match matches.values_of("property") {
+        Some(values) => {
+            let mut changes = BTreeMap::new();
+            for value in values {
+                let mut split = value.splitn(2, '=');
+                match (split.next(), split.next()) {
+                    (Some(key), Some(value)) => changes.insert(key.to_string(), value.to_string()),
+                    _ => return Err(CliError::InvalidProperty(value.to_string())),
+                };
+            }
+            client.set_properties(id, changes).await?
+        }
+        None => client.get_properties(id).await?,
+    };
+
+    for (key, value) in props.iter() {
+        println!("{}={}", key, value);
+    }
+
+    Ok(())
+}
+
+async fn run(matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let client = Client::connect(matches.value_of("socket").unwrap_or(SOCKET_PATH)).await?;
+
+    match matches.subcommand() {
+        Some(("list", _)) => client.list_servers().await?.iter().for_each(print_server),
+        Some(("start", sub)) => print_server(&client.start(parse_id(sub)?).await?),
+        Some(("stop", sub)) => print_server(&client.stop(parse_id(sub)?, parse_delay(sub)?).await?),
+        Some(("restart", sub)) => {
+            print_server(&client.restart(parse_id(sub)?, parse_delay(sub)?).await?)
+        }
+        Some(("cancel", sub)) => print_server(&client.cancel_shutdown(parse_id(sub)?).await?),
+        Some(("preflight", sub)) => {
+            let errors = client.preflight(parse_id(sub)?).await?;
+            if errors.is_empty() {
+                println!("ready to start");
+            }
+            errors.iter().for_each(|e| println!("{}", e));
+        }
+        Some(("accept-eula", sub)) => print_server(&client.accept_eula(parse_id(sub)?).await?),
+        Some(("props", sub)) => properties(&client, sub).await?,
+        Some(("access", sub)) => access(&client, sub).await?,
+        Some(("backup", sub)) => {
+            let info = client.backup(parse_id(sub)?).await?;
+            println!("{} ({} bytes)", info.name, info.size);
+        }
+        Some(("backups", sub)) => client
+            .list_backups(parse_id(sub)?)
+            .await?
+            .iter()
+            .for_each(print_backup),
+        Some(("delete-backup", sub)) => client
+            .delete_backup(parse_id(sub)?, sub.value_of("name").unwrap())
+            .await?
+            .iter()
+            .for_each(print_backup),
+        Some(("verify-backups", sub)) => {
+            for result in client.verify_backups(parse_id(sub)?).await?.iter() {
+                if result.problems.is_empty() {
+                    println!("{}: ok", result.name);
+                } else {
+                    println!("{}:", result.name);
+                    result.problems.iter().for_each(|p| println!("  {}", p));
+                }
+            }
+        }
+        Some(("restore", sub)) => print_server(
+            &client
+                .restore_backup(parse_id(sub)?, sub.value_of("name").unwrap())
+                .await?,
+        ),
+        Some(("schedules", sub)) => {
+            let id = parse_id(sub)?;
+            let schedules = match (sub.value_of("action"), sub.value_of("name")) {
+                (Some("run"), Some(name)) => client.run_schedule(id, name).await?,
+                (Some("enable"), Some(name)) => client.enable_schedule(id, name).await?,
+                (Some("disable"), Some(name)) => client.disable_schedule(id, name).await?,
+                _ => client.list_schedules(id).await?,
+            };
+            schedules.iter().for_each(print_schedule);
+        }
+        Some(("java", _)) => {
+            for runtime in client.java_runtimes().await?.iter() {
+                let vendor = runtime.vendor.as_deref().unwrap_or("unknown vendor");
+                println!("{} ({}) {}", runtime.version, vendor, runtime.path);
+            }
+        }
+        Some(("drift", _)) => {
+            for d in client.sync_drift().await?.iter() {
+                println!("{}: server {} {}", d.group, d.server, d.list);
+                d.missing.iter().for_each(|e| println!("  + {}", e.name));
+                d.extra.iter().for_each(|e| println!("  - {}", e.name));
+            }
+        }
+        Some(("rcon", sub)) => {
+            let cmd = sub
+                .values_of("command")
+                .unwrap()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let output = client.rcon(parse_id(sub)?, &cmd).await?;
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() {}
+async fn main() {
+    pretty_env_logger::init_custom_env("MINECTL_LOG");
+    let id = Arg::new("id")
+        .value_name("ID")
+        .about("the server id")
+        .required(true);
+    let backup = Arg::new("name")
+        .value_name("NAME")
+        .about("the backup's name")
+        .required(true);
+    let delay = Arg::new("delay")
+        .long("in")
+        .value_name("SECONDS")
+        .about("warns players and waits this long first")
+        .takes_value(true);
+    let matches = App::new("minectl")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .short('s')
+                .value_name("FILE")
+                .about("sets the path of the daemon's control socket")
+                .takes_value(true),
+        )
+        .subcommand(App::new("list").about("lists all servers"))
+        .subcommand(App::new("start").about("starts a server").arg(id.clone()))
+        .subcommand(
+            App::new("stop")
+                .about("stops a server")
+                .arg(id.clone())
+                .arg(delay.clone()),
+        )
+        .subcommand(
+            App::new("restart")
+                .about("stops a server and starts it again")
+                .arg(id.clone())
+                .arg(delay),
+        )
+        .subcommand(
+            App::new("cancel")
+                .about("cancels a delayed stop or restart")
+                .arg(id.clone()),
+        )
+        .subcommand(
+            App::new("preflight")
+                .about("checks that a server is able to start")
+                .arg(id.clone()),
+        )
+        .subcommand(
+            App::new("accept-eula")
+                .about("accepts the minecraft eula for a server")
+                .arg(id.clone()),
+        )
+        .subcommand(
+            App::new("props")
+                .about("lists or sets a server's server.properties")
+                .arg(id.clone())
+                .arg(
+                    Arg::new("property")
+                        .value_name("KEY=VALUE")
+                        .about("properties to set")
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            App::new("access")
+                .about("lists or changes a server's whitelist, ops or bans")
+                .arg(id.clone())
+                .arg(
+                    Arg::new("list")
+                        .value_name("LIST")
+                        .about("the list to use")
+                        .possible_values(&["whitelist", "ops", "bans", "ip-bans"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("action")
+                        .value_name("ACTION")
+                        .about("whether to add or remove a player")
+                        .possible_values(&["add", "remove"])
+                        .requires("name"),
+                )
+                .arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .about("the player's name, or an address for ip-bans"),
+                ),
+        )
+        .subcommand(
+            App::new("backup")
+                .about("backs up a server")
+                .arg(id.clone()),
+        )
+        .subcommand(
+            App::new("backups")
+                .about("lists a server's backups, newest first")
+                .arg(id.clone()),
+        )
+        .subcommand(
+            App::new("delete-backup")
+                .about("deletes one of a server's backups")
+                .arg(id.clone())
+                .arg(backup.clone()),
+        )
+        .subcommand(
+            App::new("verify-backups")
+                .about("checks that none of a server's backups are damaged")
+                .arg(id.clone()),
+        )
+        .subcommand(
+            App::new("restore")
+                .about("restores a stopped server from a backup")
+                .arg(id.clone())
+                .arg(backup),
+        )
+        .subcommand(
+            App::new("schedules")
+                .about("lists a server's schedules, or runs, enables or disables one")
+                .arg(id.clone())
+                .arg(
+                    Arg::new("action")
+                        .value_name("ACTION")
+                        .about("what to do with the schedule")
+                        .possible_values(&["run", "enable", "disable"])
+                        .requires("name"),
+                )
+                .arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .about("the schedule's name"),
+                ),
+        )
+        .subcommand(
+            App::new("drift").about("shows how sync group members differ from their source"),
+        )
+        .subcommand(App::new("java").about("lists the java installations the daemon found"))
+        .subcommand(
+            App::new("rcon")
+                .about("runs a console command over rcon")
+                .arg(id)
+                .arg(
+                    Arg::new("command")
+                        .value_name("COMMAND")
+                        .about("the command to run")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches).await {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
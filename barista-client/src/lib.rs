@@ -0,0 +1,667 @@
+use barista::access::{AccessEntry, AccessList, SyncDrift};
+use barista::backup::{BackupInfo, BackupVerification};
+use barista::command::*;
+use barista::java::JavaRuntime;
+use barista::preflight::PreflightError;
+use barista::schedule::ScheduleInfo;
+use barista::server::ServerData;
+use barista::socket::{MAX_MESSAGE_LEN, SOCKET_PATH};
+use log::{trace, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+use tokio::prelude::*;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::{delay_for, timeout};
+
+static DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+// backups of big worlds can take a while
+static BACKUP_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+static MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+type Reply = oneshot::Sender<Result<CommandResponse, ClientError>>;
+type Request = (Command, Reply);
+type Subscribers = Arc<Mutex<Vec<UnboundedSender<CommandResponse>>>>;
+
+#[derive(Debug)]
+pub enum ClientError {
+    IoError(io::Error),
+    ParseError(serde_cbor::Error),
+    CommandError(CommandError),
+    UnexpectedResponse(Box<CommandResponse>),
+    Disconnected,
+    Timeout,
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl From<serde_cbor::Error> for ClientError {
+    fn from(e: serde_cbor::Error) -> Self {
+        Self::ParseError(e)
+    }
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Self::IoError(e) => format!("io error: {}", e),
+            Self::ParseError(e) => format!("failed to parse/serialize message: {}", e),
+            Self::CommandError(e) => format!("{}", e),
+            Self::UnexpectedResponse(res) => format!("unexpected response: {:?}", res),
+            Self::Disconnected => "disconnected from daemon".to_string(),
+            Self::Timeout => "timed out waiting for a response".to_string(),
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+async fn read_frame(rd: &mut OwnedReadHalf) -> Result<CommandResponse, ClientError> {
+    let len = rd.read_u32().await?;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message too long: {} bytes", len),
+        )
+        .into());
+    }
+
+    let mut buf = vec![0; len as usize];
+    rd.read_exact(&mut buf).await?;
+    Ok(serde_cbor::from_slice(&buf)?)
+}
+
+async fn write_frame(wr: &mut OwnedWriteHalf, cmd: &Command) -> Result<(), ClientError> {
+    let bytes = serde_cbor::to_vec(cmd)?;
+    wr.write_u32(bytes.len() as u32).await?;
+    wr.write_all(&bytes).await?;
+    Ok(())
+}
+
+// reading a frame isn't cancel safe, so it gets its own task instead of being raced with
+// outgoing requests
+async fn read_frames(mut rd: OwnedReadHalf, tx: UnboundedSender<CommandResponse>) {
+    loop {
+        match read_frame(&mut rd).await {
+            Ok(res) => {
+                if tx.send(res).is_err() {
+                    return;
+                }
+            }
+            Err(e) => return trace!("daemon connection closed: {}", e),
+        }
+    }
+}
+
+struct Connection {
+    path: PathBuf,
+    requests: UnboundedReceiver<Request>,
+    queued: Vec<Request>,
+    subscribers: Subscribers,
+    next_tag: u64,
+}
+
+impl Connection {
+    // returns false if the request couldn't be sent and the connection should be dropped
+    async fn send_request(
+        &mut self,
+        wr: &mut OwnedWriteHalf,
+        pending: &mut HashMap<u64, Reply>,
+        (cmd, reply): Request,
+    ) -> bool {
+        let tag = self.next_tag;
+        self.next_tag += 1;
+
+        match write_frame(wr, &Command::Tagged(tag, Box::new(cmd))).await {
+            Ok(_) => {
+                pending.insert(tag, reply);
+                true
+            }
+            Err(e) => {
+                let _ = reply.send(Err(e));
+                false
+            }
+        }
+    }
+
+    // returns false once the client has been dropped, anything left in `pending` when this
+    // returns is dropped, which fails those requests with `ClientError::Disconnected`
+    async fn serve(&mut self, stream: UnixStream) -> bool {
+        let (rd, mut wr) = stream.into_split();
+        let (tx, mut frames) = unbounded_channel();
+        tokio::spawn(read_frames(rd, tx));
+
+        let mut pending = HashMap::new();
+
+        for req in std::mem::take(&mut self.queued) {
+            if !self.send_request(&mut wr, &mut pending, req).await {
+                return true;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                req = self.requests.recv() => match req {
+                    Some(req) => {
+                        if !self.send_request(&mut wr, &mut pending, req).await {
+                            return true;
+                        }
+                    }
+                    None => return false,
+                },
+                frame = frames.recv() => match frame {
+                    Some(CommandResponse::Tagged(tag, res)) => {
+                        if let Some(reply) = pending.remove(&tag) {
+                            let _ = reply.send(Ok(*res));
+                        }
+                    }
+                    Some(res) => {
+                        let mut subscribers = self.subscribers.lock().unwrap();
+                        subscribers.retain(|s| s.send(res.clone()).is_ok());
+                    }
+                    None => return true,
+                },
+            }
+        }
+    }
+
+    async fn reconnect(&mut self) -> Option<UnixStream> {
+        let mut delay = Duration::from_secs(1);
+
+        loop {
+            tokio::select! {
+                _ = delay_for(delay) => {}
+                req = self.requests.recv() => {
+                    match req {
+                        Some(req) => self.queued.push(req),
+                        None => return None,
+                    }
+                    continue;
+                }
+            }
+
+            match UnixStream::connect(&self.path).await {
+                Ok(stream) => return Some(stream),
+                Err(e) => {
+                    warn!("failed to reconnect to daemon: {}", e);
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
+    }
+
+    async fn run(mut self, mut stream: UnixStream) {
+        while self.serve(stream).await {
+            warn!("lost connection to daemon, reconnecting");
+            stream = match self.reconnect().await {
+                Some(s) => s,
+                None => return,
+            };
+        }
+    }
+}
+
+pub struct Client {
+    requests: UnboundedSender<Request>,
+    subscribers: Subscribers,
+    timeout: Duration,
+}
+
+impl Client {
+    pub async fn connect<P: Into<PathBuf>>(path: P) -> Result<Self, ClientError> {
+        let path = path.into();
+        let stream = UnixStream::connect(&path).await?;
+
+        let (tx, rx) = unbounded_channel();
+        let subscribers = Subscribers::default();
+
+        let conn = Connection {
+            path,
+            requests: rx,
+            queued: vec![],
+            subscribers: subscribers.clone(),
+            next_tag: 0,
+        };
+        tokio::spawn(conn.run(stream));
+
+        Ok(Self {
+            requests: tx,
+            subscribers,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    pub async fn connect_default() -> Result<Self, ClientError> {
+        Self::connect(SOCKET_PATH).await
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub async fn send(&self, cmd: Command) -> Result<CommandResponse, ClientError> {
+        self.send_timeout(cmd, self.timeout).await
+    }
+
+    pub async fn send_timeout(
+        &self,
+        cmd: Command,
+        duration: Duration,
+    ) -> Result<CommandResponse, ClientError> {
+        let (tx, rx) = oneshot::channel();
+        self.requests
+            .send((cmd, tx))
+            .map_err(|_| ClientError::Disconnected)?;
+
+        match timeout(duration, rx).await {
+            Ok(Ok(Ok(CommandResponse::Error(e)))) => Err(ClientError::CommandError(e)),
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => Err(ClientError::Disconnected),
+            Err(_) => Err(ClientError::Timeout),
+        }
+    }
+
+    async fn send_server_cmd(&self, cmd: Command) -> Result<ServerData, ClientError> {
+        match self.send(cmd).await? {
+            CommandResponse::UpdateServer(_, data) => Ok(data),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    pub async fn list_servers(&self) -> Result<Vec<ServerData>, ClientError> {
+        match self.send(Command::GetServers).await? {
+            CommandResponse::UpdateServers(servers) => Ok(servers),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    pub async fn start(&self, id: usize) -> Result<ServerData, ClientError> {
+        self.send_server_cmd(Command::StartServer(id)).await
+    }
+
+    // with a delay in seconds, the server counts down and warns players first
+    pub async fn stop(&self, id: usize, delay: Option<u64>) -> Result<ServerData, ClientError> {
+        self.send_server_cmd(Command::StopServer(id, delay)).await
+    }
+
+    // the server is started again once it has stopped
+    pub async fn restart(&self, id: usize, delay: Option<u64>) -> Result<ServerData, ClientError> {
+        self.send_server_cmd(Command::RestartServer(id, delay))
+            .await
+    }
+
+    pub async fn cancel_shutdown(&self, id: usize) -> Result<ServerData, ClientError> {
+        self.send_server_cmd(Command::CancelShutdown(id)).await
+    }
+
+    pub async fn accept_eula(&self, id: usize) -> Result<ServerData, ClientError> {
+        self.send_server_cmd(Command::AcceptEula(id)).await
+    }
+
+    async fn send_access_cmd(&self, cmd: Command) -> Result<Vec<AccessEntry>, ClientError> {
+        match self.send(cmd).await? {
+            CommandResponse::AccessList(_, _, entries) => Ok(entries),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    pub async fn access_list(
+        &self,
+        id: usize,
+        list: AccessList,
+    ) -> Result<Vec<AccessEntry>, ClientError> {
+        self.send_access_cmd(Command::GetAccessList(id, list)).await
+    }
+
+    // returns the list after the change
+    pub async fn add_access(
+        &self,
+        id: usize,
+        list: AccessList,
+        name: &str,
+    ) -> Result<Vec<AccessEntry>, ClientError> {
+        self.send_access_cmd(Command::AddAccessEntry(id, list, name.to_string()))
+            .await
+    }
+
+    pub async fn remove_access(
+        &self,
+        id: usize,
+        list: AccessList,
+        name: &str,
+    ) -> Result<Vec<AccessEntry>, ClientError> {
+        self.send_access_cmd(Command::RemoveAccessEntry(id, list, name.to_string()))
+            .await
+    }
+
+    pub async fn sync_drift(&self) -> Result<Vec<SyncDrift>, ClientError> {
+        match self.send(Command::GetSyncDrift).await? {
+            CommandResponse::SyncDrift(drift) => Ok(drift),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    // progress is sent to subscribers as `CommandResponse::BackupProgress`
+    pub async fn backup(&self, id: usize) -> Result<BackupInfo, ClientError> {
+        match self
+            .send_timeout(Command::Backup(id), BACKUP_TIMEOUT)
+            .await?
+        {
+            CommandResponse::Backup(_, info) => Ok(info),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    async fn send_backups_cmd(&self, cmd: Command) -> Result<Vec<BackupInfo>, ClientError> {
+        match self.send(cmd).await? {
+            CommandResponse::Backups(_, backups) => Ok(backups),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    pub async fn list_backups(&self, id: usize) -> Result<Vec<BackupInfo>, ClientError> {
+        self.send_backups_cmd(Command::ListBackups(id)).await
+    }
+
+    // returns the backups that are left
+    pub async fn delete_backup(
+        &self,
+        id: usize,
+        name: &str,
+    ) -> Result<Vec<BackupInfo>, ClientError> {
+        self.send_backups_cmd(Command::DeleteBackup(id, name.to_string()))
+            .await
+    }
+
+    // the server has to be stopped
+    pub async fn restore_backup(&self, id: usize, name: &str) -> Result<ServerData, ClientError> {
+        let cmd = Command::RestoreBackup(id, name.to_string());
+        match self.send_timeout(cmd, BACKUP_TIMEOUT).await? {
+            CommandResponse::UpdateServer(_, data) => Ok(data),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    async fn send_schedule_cmd(
+        &self,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<Vec<ScheduleInfo>, ClientError> {
+        match self.send_timeout(cmd, timeout).await? {
+            CommandResponse::Schedules(_, schedules) => Ok(schedules),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    pub async fn list_schedules(&self, id: usize) -> Result<Vec<ScheduleInfo>, ClientError> {
+        self.send_schedule_cmd(Command::ListSchedules(id), DEFAULT_TIMEOUT)
+            .await
+    }
+
+    // waits for it to finish, which can take as long as a backup
+    pub async fn run_schedule(
+        &self,
+        id: usize,
+        name: &str,
+    ) -> Result<Vec<ScheduleInfo>, ClientError> {
+        self.send_schedule_cmd(
+            Command::RunScheduleNow(id, name.to_string()),
+            BACKUP_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn enable_schedule(
+        &self,
+        id: usize,
+        name: &str,
+    ) -> Result<Vec<ScheduleInfo>, ClientError> {
+        self.send_schedule_cmd(
+            Command::EnableSchedule(id, name.to_string()),
+            DEFAULT_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn disable_schedule(
+        &self,
+        id: usize,
+        name: &str,
+    ) -> Result<Vec<ScheduleInfo>, ClientError> {
+        self.send_schedule_cmd(
+            Command::DisableSchedule(id, name.to_string()),
+            DEFAULT_TIMEOUT,
+        )
+        .await
+    }
+
+    // reads every backup back, this can take a while for large repositories
+    pub async fn verify_backups(&self, id: usize) -> Result<Vec<BackupVerification>, ClientError> {
+        match self
+            .send_timeout(Command::VerifyBackups(id), BACKUP_TIMEOUT)
+            .await?
+        {
+            CommandResponse::BackupVerification(_, results) => Ok(results),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    // everything that would stop the server from starting, empty if it's ready
+    pub async fn preflight(&self, id: usize) -> Result<Vec<PreflightError>, ClientError> {
+        match self.send(Command::Preflight(id)).await? {
+            CommandResponse::Preflight(_, errors) => Ok(errors),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    // the daemon looks for them again first, so newly installed ones show up
+    pub async fn java_runtimes(&self) -> Result<Vec<JavaRuntime>, ClientError> {
+        match self.send(Command::ListJavaRuntimes).await? {
+            CommandResponse::JavaRuntimes(runtimes) => Ok(runtimes),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    // runs a console command over rcon and returns its output
+    pub async fn rcon(&self, id: usize, cmd: &str) -> Result<String, ClientError> {
+        match self.send(Command::Rcon(id, cmd.to_string())).await? {
+            CommandResponse::RconResponse(_, output) => Ok(output),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    pub async fn get_properties(&self, id: usize) -> Result<BTreeMap<String, String>, ClientError> {
+        match self.send(Command::GetProperties(id)).await? {
+            CommandResponse::Properties(_, props) => Ok(props),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    // returns every property after the change
+    pub async fn set_properties(
+        &self,
+        id: usize,
+        changes: BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>, ClientError> {
+        match self.send(Command::SetProperties(id, changes)).await? {
+            CommandResponse::Properties(_, props) => Ok(props),
+            res => Err(ClientError::UnexpectedResponse(Box::new(res))),
+        }
+    }
+
+    // every update the daemon pushes to its clients, e.g. `CommandResponse::UpdateServer` when
+    // a server changes status
+    pub fn subscribe(&self) -> UnboundedReceiver<CommandResponse> {
+        let (tx, rx) = unbounded_channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use barista::config::ServerConfig;
+    use std::path::Path;
+    use tokio::net::UnixListener;
+
+    // stands in for the daemon's end of the socket
+    struct Daemon {
+        path: PathBuf,
+        listener: UnixListener,
+    }
+
+    impl Daemon {
+        fn bind(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "barista-client-{}-{}.sock",
+                std::process::id(),
+                name
+            ));
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap();
+            Self { path, listener }
+        }
+
+        async fn accept(&mut self) -> UnixStream {
+            self.listener.accept().await.unwrap().0
+        }
+    }
+
+    impl Drop for Daemon {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    async fn read_cmd(stream: &mut UnixStream) -> (u64, Command) {
+        let len = stream.read_u32().await.unwrap();
+        let mut buf = vec![0; len as usize];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        match serde_cbor::from_slice(&buf).unwrap() {
+            Command::Tagged(tag, cmd) => (tag, *cmd),
+            cmd => panic!("untagged command {:?}", cmd),
+        }
+    }
+
+    async fn write_res(stream: &mut UnixStream, res: &CommandResponse) {
+        let bytes = serde_cbor::to_vec(res).unwrap();
+        stream.write_u32(bytes.len() as u32).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+    }
+
+    fn tagged(tag: u64, res: CommandResponse) -> CommandResponse {
+        CommandResponse::Tagged(tag, Box::new(res))
+    }
+
+    fn server() -> ServerData {
+        let config = "name: survival\ndir: /srv/survival\njar: server.jar\nargs: []";
+        let config: ServerConfig = serde_yaml::from_str(config).unwrap();
+        ServerData::new(0, config)
+    }
+
+    async fn connect(path: &Path) -> Client {
+        let mut client = Client::connect(path).await.unwrap();
+        client.set_timeout(Duration::from_secs(10));
+        client
+    }
+
+    #[tokio::test]
+    async fn matches_responses_to_tags() {
+        let mut daemon = Daemon::bind("tags");
+        let client = connect(&daemon.path).await;
+        let mut stream = daemon.accept().await;
+
+        let daemon = async move {
+            let mut cmds = vec![read_cmd(&mut stream).await, read_cmd(&mut stream).await];
+            cmds.sort_by_key(|(tag, _)| *tag);
+
+            // answered in the opposite order to how they were sent
+            for (tag, cmd) in cmds.into_iter().rev() {
+                let res = match cmd {
+                    Command::GetServers => CommandResponse::UpdateServers(vec![]),
+                    Command::ListJavaRuntimes => CommandResponse::JavaRuntimes(vec![]),
+                    cmd => panic!("unexpected command {:?}", cmd),
+                };
+                write_res(&mut stream, &tagged(tag, res)).await;
+            }
+            stream
+        };
+
+        let (servers, runtimes, _) =
+            tokio::join!(client.list_servers(), client.java_runtimes(), daemon);
+        assert!(servers.unwrap().is_empty());
+        assert!(runtimes.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn queues_requests_while_disconnected() {
+        let mut daemon = Daemon::bind("reconnect");
+        let client = connect(&daemon.path).await;
+
+        let mut stream = daemon.accept().await;
+        let (res, _) = tokio::join!(client.list_servers(), async {
+            read_cmd(&mut stream).await;
+            drop(stream);
+        });
+        match res {
+            Err(ClientError::Disconnected) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // sent before the client has reconnected
+        let daemon = async {
+            let mut stream = daemon.accept().await;
+            let (tag, cmd) = read_cmd(&mut stream).await;
+            assert!(matches!(cmd, Command::StartServer(0)));
+            write_res(
+                &mut stream,
+                &tagged(tag, CommandResponse::UpdateServer(0, server())),
+            )
+            .await;
+            stream
+        };
+        let (res, _) = tokio::join!(client.start(0), daemon);
+        assert_eq!(res.unwrap().name, "survival");
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_pushed_updates() {
+        let mut daemon = Daemon::bind("subscribe");
+        let client = connect(&daemon.path).await;
+        let mut updates = client.subscribe();
+        let mut stream = daemon.accept().await;
+
+        let daemon = async move {
+            let (tag, _) = read_cmd(&mut stream).await;
+            write_res(&mut stream, &CommandResponse::UpdateServer(0, server())).await;
+            write_res(
+                &mut stream,
+                &tagged(tag, CommandResponse::UpdateServers(vec![])),
+            )
+            .await;
+            stream
+        };
+        let (res, _) = tokio::join!(client.list_servers(), daemon);
+        res.unwrap();
+
+        match timeout(Duration::from_secs(5), updates.recv()).await {
+            Ok(Some(CommandResponse::UpdateServer(0, data))) => assert_eq!(data, server()),
+            res => panic!("unexpected update: {:?}", res),
+        }
+        // the reply to the request isn't pushed as well
+        assert!(updates.try_recv().is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use super::properties::Properties;
+use super::GlobalState;
+use barista::access::{AccessEntry, AccessList};
+use barista::command::*;
+use chrono::Utc;
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::delay_for;
+
+static PROFILE_URL: &str = "https://api.mojang.com/users/profiles/minecraft/";
+// how long to wait for a running server to write the change to disk
+static APPLY_TIMEOUT: Duration = Duration::from_secs(5);
+static APPLY_INTERVAL: Duration = Duration::from_millis(250);
+
+fn path(dir: &Path, list: AccessList) -> PathBuf {
+    dir.join(list.file_name())
+}
+
+fn load(dir: &Path, list: AccessList) -> Result<Vec<Value>, CommandError> {
+    let contents = match fs::read_to_string(path(dir, list)) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    if contents.trim().is_empty() {
+        return Ok(vec![]);
+    }
+
+    serde_json::from_str(&contents).map_err(|e| CommandError::InvalidAccessList(e.to_string()))
+}
+
+fn save(dir: &Path, list: AccessList, entries: &[Value]) -> Result<(), CommandError> {
+    let contents = serde_json::to_string_pretty(entries)
+        .map_err(|e| CommandError::InvalidAccessList(e.to_string()))?;
+
+    let path = path(dir, list);
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+fn key(list: AccessList) -> &'static str {
+    match list {
+        AccessList::BannedIps => "ip",
+        _ => "name",
+    }
+}
+
+fn to_entry(list: AccessList, value: &Value) -> Option<AccessEntry> {
+    Some(AccessEntry {
+        uuid: value["uuid"].as_str().map(|u| u.to_string()),
+        name: value[key(list)].as_str()?.to_string(),
+    })
+}
+
+fn matches(list: AccessList, value: &Value, name: &str) -> bool {
+    matches!(value[key(list)].as_str(), Some(n) if n.eq_ignore_ascii_case(name))
+}
+
+pub fn entries(dir: &Path, list: AccessList) -> Result<Vec<AccessEntry>, CommandError> {
+    Ok(load(dir, list)?
+        .iter()
+        .filter_map(|v| to_entry(list, v))
+        .collect())
+}
+
+fn contains(dir: &Path, list: AccessList, name: &str) -> Result<bool, CommandError> {
+    Ok(load(dir, list)?.iter().any(|v| matches(list, v, name)))
+}
+
+// names end up in console commands, so anything else is refused
+pub fn validate(list: AccessList, name: &str) -> Result<(), CommandError> {
+    let valid = match list {
+        AccessList::BannedIps => name.parse::<IpAddr>().is_ok(),
+        _ => {
+            (1..=16).contains(&name.len())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(CommandError::InvalidPlayer(name.to_string()))
+    }
+}
+
+pub fn console_command(list: AccessList, add: bool, name: &str) -> String {
+    let cmd = match (list, add) {
+        (AccessList::Whitelist, true) => "whitelist add",
+        (AccessList::Whitelist, false) => "whitelist remove",
+        (AccessList::Ops, true) => "op",
+        (AccessList::Ops, false) => "deop",
+        (AccessList::BannedPlayers, true) => "ban",
+        (AccessList::BannedPlayers, false) => "pardon",
+        (AccessList::BannedIps, true) => "ban-ip",
+        (AccessList::BannedIps, false) => "pardon-ip",
+    };
+
+    format!("{} {}", cmd, name)
+}
+
+fn format_uuid(hex: &str) -> String {
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+// the uuid an offline mode server gives a player, a v3 uuid of `OfflinePlayer:<name>`
+fn offline_uuid(name: &str) -> String {
+    let mut hash = md5::compute(format!("OfflinePlayer:{}", name)).0;
+    hash[6] = (hash[6] & 0x0f) | 0x30;
+    hash[8] = (hash[8] & 0x3f) | 0x80;
+
+    let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    format_uuid(&hex)
+}
+
+async fn lookup(name: &str) -> Result<AccessEntry, CommandError> {
+    let failed = |e: reqwest::Error| CommandError::LookupFailed(e.to_string());
+
+    let res = reqwest::get(&format!("{}{}", PROFILE_URL, name))
+        .await
+        .map_err(failed)?;
+
+    // unknown names get an empty response
+    if res.status() == reqwest::StatusCode::NO_CONTENT
+        || res.status() == reqwest::StatusCode::NOT_FOUND
+    {
+        return Err(CommandError::UnknownPlayer(name.to_string()));
+    }
+
+    let body = res
+        .error_for_status()
+        .map_err(failed)?
+        .text()
+        .await
+        .map_err(failed)?;
+    let profile: Value =
+        serde_json::from_str(&body).map_err(|e| CommandError::LookupFailed(e.to_string()))?;
+
+    match (profile["id"].as_str(), profile["name"].as_str()) {
+        (Some(id), Some(name)) if id.len() == 32 => Ok(AccessEntry {
+            uuid: Some(format_uuid(id)),
+            name: name.to_string(),
+        }),
+        _ => Err(CommandError::LookupFailed(
+            "invalid profile response".to_string(),
+        )),
+    }
+}
+
+async fn resolve(dir: &Path, list: AccessList, name: &str) -> Result<AccessEntry, CommandError> {
+    if list == AccessList::BannedIps {
+        return Ok(AccessEntry {
+            uuid: None,
+            name: name.to_string(),
+        });
+    }
+
+    let online = match Properties::load(dir) {
+        Ok(props) => props.get("online-mode") != Some("false"),
+        Err(e) => {
+            warn!("failed to read server.properties: {}", e);
+            true
+        }
+    };
+
+    if online {
+        lookup(name).await
+    } else {
+        Ok(AccessEntry {
+            uuid: Some(offline_uuid(name)),
+            name: name.to_string(),
+        })
+    }
+}
+
+// the same fields the server writes itself
+fn new_entry(list: AccessList, entry: &AccessEntry) -> Value {
+    let created = Utc::now().format("%Y-%m-%d %H:%M:%S +0000").to_string();
+
+    match list {
+        AccessList::Whitelist => json!({ "uuid": entry.uuid, "name": entry.name }),
+        AccessList::Ops => json!({
+            "uuid": entry.uuid,
+            "name": entry.name,
+            "level": 4,
+            "bypassesPlayerLimit": false,
+        }),
+        AccessList::BannedPlayers => json!({
+            "uuid": entry.uuid,
+            "name": entry.name,
+            "created": created,
+            "source": "mined",
+            "expires": "forever",
+            "reason": "Banned by an operator.",
+        }),
+        AccessList::BannedIps => json!({
+            "ip": entry.name,
+            "created": created,
+            "source": "mined",
+            "expires": "forever",
+            "reason": "Banned by an operator.",
+        }),
+    }
+}
+
+pub fn add_entry(dir: &Path, list: AccessList, entry: &AccessEntry) -> Result<(), CommandError> {
+    let mut entries = load(dir, list)?;
+    if entries.iter().any(|v| matches(list, v, &entry.name)) {
+        return Ok(());
+    }
+
+    entries.push(new_entry(list, entry));
+    save(dir, list, &entries)
+}
+
+pub fn remove_entry(dir: &Path, list: AccessList, name: &str) -> Result<(), CommandError> {
+    let mut entries = load(dir, list)?;
+    let len = entries.len();
+    entries.retain(|v| !matches(list, v, name));
+
+    if entries.len() != len {
+        save(dir, list, &entries)?;
+    }
+    Ok(())
+}
+
+// sends the change to the console if the server is running, returning the server's directory
+fn send_change(
+    state: &GlobalState,
+    id: usize,
+    list: AccessList,
+    name: &str,
+    add: bool,
+) -> Result<(PathBuf, bool), CommandError> {
+    let mut lock = state.write()?;
+    let server = lock
+        .servers
+        .get_mut(id)
+        .ok_or(CommandError::NonExistentServer(id))?;
+    let dir = PathBuf::from(&server.data.config.dir);
+
+    if !server.is_running() {
+        return Ok((dir, false));
+    }
+
+    server.send_console(&console_command(list, add, name))?;
+    Ok((dir, true))
+}
+
+// applies a change from a sync group, the entry already has its uuid so nothing is looked up
+pub fn sync_entry(
+    state: &GlobalState,
+    id: usize,
+    list: AccessList,
+    entry: &AccessEntry,
+    add: bool,
+) -> Result<(), CommandError> {
+    validate(list, &entry.name)?;
+
+    let (dir, running) = send_change(state, id, list, &entry.name, add)?;
+    match (running, add) {
+        (true, _) => Ok(()),
+        (false, true) => add_entry(&dir, list, entry),
+        (false, false) => remove_entry(&dir, list, &entry.name),
+    }
+}
+
+pub async fn update(
+    state: GlobalState,
+    id: usize,
+    list: AccessList,
+    name: String,
+    add: bool,
+) -> CommandResult {
+    validate(list, &name)?;
+
+    let (dir, running) = send_change(&state, id, list, &name, add)?;
+
+    if running {
+        // the server saves the file on its own time, so wait for it to show up
+        let mut waited = Duration::from_secs(0);
+        while contains(&dir, list, &name)? != add {
+            if waited >= APPLY_TIMEOUT {
+                return Err(CommandError::AccessListNotUpdated(id, list));
+            }
+            delay_for(APPLY_INTERVAL).await;
+            waited += APPLY_INTERVAL;
+        }
+    } else if add {
+        let entry = resolve(&dir, list, &name).await?;
+        add_entry(&dir, list, &entry)?;
+    } else {
+        remove_entry(&dir, list, &name)?;
+    }
+
+    info!(
+        "{} {} {} the {} of server {}",
+        if add { "added" } else { "removed" },
+        name,
+        if add { "to" } else { "from" },
+        list,
+        id
+    );
+
+    Ok(CommandResponse::AccessList(id, list, entries(&dir, list)?))
+}
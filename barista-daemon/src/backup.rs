@@ -0,0 +1,594 @@
+use super::dedup::{self, Repo};
+use super::retention;
+use super::{broadcast, GlobalState, Server, ServerContext};
+use barista::backup::{BackupInfo, BackupProgress, BackupVerification};
+use barista::command::*;
+use barista::config::{BackupConfig, BackupMode, Event, RetentionConfig};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::broadcast::{self as channel, RecvError};
+use tokio::time::timeout;
+
+static SAVE_TIMEOUT: Duration = Duration::from_secs(120);
+static ZSTD_LEVEL: i32 = 3;
+static EXTENSION: &str = ".tar.zst";
+static NAME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+struct Job {
+    dir: PathBuf,
+    backup_dir: PathBuf,
+    worlds: Vec<String>,
+    mode: BackupMode,
+    // only set if the server is running
+    console: Option<channel::Receiver<String>>,
+}
+
+pub fn backup_dir(dir: &Path, config: &BackupConfig) -> PathBuf {
+    match &config.dir {
+        Some(backups) => dir.join(backups),
+        None => dir.join("backups"),
+    }
+}
+
+fn progress(state: &GlobalState, id: usize, progress: BackupProgress) {
+    if let Ok(lock) = state.read() {
+        broadcast(&lock.tx, &CommandResponse::BackupProgress(id, progress));
+    }
+}
+
+fn console(state: &GlobalState, id: usize, cmd: &str) -> Result<(), CommandError> {
+    let mut lock = state.write()?;
+    lock.servers[id].send_console(cmd)
+}
+
+// anything that changes the backup directory holds this, so the repository is never collected
+// while a snapshot is being written
+fn claim(server: &mut Server, id: usize) -> Result<(), CommandError> {
+    if server.backing_up {
+        return Err(CommandError::BackupInProgress(id));
+    }
+    if server.restoring {
+        return Err(CommandError::RestoreInProgress(id));
+    }
+    server.backing_up = true;
+    Ok(())
+}
+
+fn release(state: &GlobalState, id: usize) {
+    if let Ok(mut lock) = state.write() {
+        lock.servers[id].backing_up = false;
+    }
+}
+
+fn begin(state: &GlobalState, id: usize) -> Result<Job, CommandError> {
+    let mut lock = state.write()?;
+    let server = lock
+        .servers
+        .get_mut(id)
+        .ok_or(CommandError::NonExistentServer(id))?;
+    claim(server, id)?;
+
+    let config = &server.data.config;
+    let dir = PathBuf::from(&config.dir);
+    Ok(Job {
+        backup_dir: backup_dir(&dir, &config.backup),
+        worlds: config.backup.worlds.clone(),
+        mode: config.backup.mode,
+        dir,
+        console: server.console(),
+    })
+}
+
+fn finish(state: &GlobalState, id: usize, res: &Result<BackupInfo, CommandError>) {
+    if let Ok(mut lock) = state.write() {
+        let server = &mut lock.servers[id];
+        let _ctx = ServerContext::enter(&server.data);
+        server.backing_up = false;
+
+        match res {
+            Ok(info) => {
+                info!("backed up {} to {}", server.data.name, info.name);
+                server.push_event(Event::BackupFinished);
+            }
+            Err(e) => {
+                error!("failed to back up {}: {}", server.data.name, e);
+                server.push_event(Event::BackupFailed);
+            }
+        }
+    }
+
+    let update = match res {
+        Ok(info) => BackupProgress::Finished(info.clone()),
+        Err(e) => BackupProgress::Failed(e.to_string()),
+    };
+    progress(state, id, update);
+}
+
+// flushes the world to disk and waits for the server to say it's done, saving has to be turned
+// off first
+async fn save(
+    state: &GlobalState,
+    id: usize,
+    console_rx: &mut channel::Receiver<String>,
+) -> Result<(), CommandError> {
+    console(state, id, "save-all flush")?;
+
+    let wait = async {
+        loop {
+            match console_rx.recv().await {
+                Ok(line) if line.contains("Saved the game") => return Ok(()),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => {
+                    return Err(CommandError::BackupFailed(
+                        "server stopped while saving".to_string(),
+                    ))
+                }
+            }
+        }
+    };
+
+    timeout(SAVE_TIMEOUT, wait)
+        .await
+        .unwrap_or_else(|_| Err(CommandError::BackupFailed("timed out saving".to_string())))
+}
+
+// next to every backup, in the same format as sha256sum
+pub fn checksum_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".sha256");
+    path.with_file_name(name)
+}
+
+pub fn checksum(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// the checksum an archive was saved with, if it has one
+pub fn expected_checksum(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(checksum_path(path)) {
+        Ok(sum) => Ok(sum.split_whitespace().next().map(|s| s.to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn verify_archive(path: &Path) -> io::Result<Vec<String>> {
+    let expected = match expected_checksum(path)? {
+        Some(sum) => sum,
+        None => return Ok(vec!["there's no checksum to check it against".to_string()]),
+    };
+
+    let actual = checksum(path)?;
+    if actual.eq_ignore_ascii_case(&expected) {
+        Ok(vec![])
+    } else {
+        Ok(vec![format!(
+            "checksum mismatch, expected {} but got {}",
+            expected, actual
+        )])
+    }
+}
+
+// snapshots leave their chunks behind until the repository is collected
+fn remove(backup_dir: &Path, name: &str) -> io::Result<()> {
+    if dedup::is_snapshot(name) {
+        return Repo::new(backup_dir).remove(name);
+    }
+
+    let path = backup_dir.join(name);
+    fs::remove_file(&path)?;
+
+    match fs::remove_file(checksum_path(&path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// the backup directory can be anywhere inside the server's, so it's looked for at every level
+// instead of only at the top
+fn append<W: io::Write>(
+    tar: &mut tar::Builder<W>,
+    dir: &Path,
+    path: &Path,
+    skip: &Path,
+) -> io::Result<()> {
+    let name = path.strip_prefix(dir).unwrap_or(path);
+    if !fs::symlink_metadata(path)?.is_dir() {
+        return tar.append_path_with_name(path, name);
+    }
+    if path.canonicalize()? == skip {
+        return Ok(());
+    }
+
+    tar.append_dir(name, path)?;
+    for entry in fs::read_dir(path)? {
+        append(tar, dir, &entry?.path(), skip)?;
+    }
+    Ok(())
+}
+
+// named after the time, with a number added if there's already one from the same second
+pub fn unique_name(dir: &Path, created: &DateTime<Utc>, extension: &str) -> String {
+    let stamp = created.format(NAME_FORMAT).to_string();
+    let mut name = format!("{}{}", stamp, extension);
+    let mut n = 1;
+    while dir.join(&name).exists() {
+        name = format!("{}-{}{}", stamp, n, extension);
+        n += 1;
+    }
+    name
+}
+
+fn archive(dir: &Path, backup_dir: &Path, worlds: &[String]) -> io::Result<BackupInfo> {
+    fs::create_dir_all(backup_dir)?;
+
+    let created = Utc::now();
+    let name = unique_name(backup_dir, &created, EXTENSION);
+    let path = backup_dir.join(&name);
+    // written under another name first, so a failed backup never looks like a finished one
+    let partial = backup_dir.join(format!("{}.partial", name));
+
+    let res = (|| {
+        let encoder = zstd::Encoder::new(File::create(&partial)?, ZSTD_LEVEL)?;
+        let mut tar = tar::Builder::new(encoder);
+        tar.follow_symlinks(false);
+
+        let skip = backup_dir.canonicalize()?;
+        if worlds.is_empty() {
+            for entry in fs::read_dir(dir)? {
+                append(&mut tar, dir, &entry?.path(), &skip)?;
+            }
+        } else {
+            for world in worlds.iter() {
+                append(&mut tar, dir, &dir.join(world), &skip)?;
+            }
+        }
+        tar.into_inner()?.finish()?.sync_all()?;
+
+        let sum = checksum(&partial)?;
+        fs::write(checksum_path(&path), format!("{}  {}\n", sum, name))?;
+        fs::rename(&partial, &path)?;
+        fs::metadata(&path)
+    })();
+
+    match res {
+        Ok(metadata) => Ok(BackupInfo {
+            name,
+            size: metadata.len(),
+            created: created.timestamp(),
+        }),
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            let _ = fs::remove_file(checksum_path(&path));
+            Err(e)
+        }
+    }
+}
+
+// the time is taken from the name, or the file's modification time for backups that were renamed
+fn created(name: &str, metadata: &fs::Metadata) -> i64 {
+    let stamp = name.get(..19).unwrap_or_default();
+    match NaiveDateTime::parse_from_str(stamp, NAME_FORMAT) {
+        Ok(time) => Utc.from_utc_datetime(&time).timestamp(),
+        Err(_) => metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs() as i64),
+    }
+}
+
+// newest first
+pub fn list(backup_dir: &Path) -> io::Result<Vec<BackupInfo>> {
+    let entries = match fs::read_dir(backup_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut backups = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let metadata = entry.metadata()?;
+        if !name.ends_with(EXTENSION) || !metadata.is_file() {
+            continue;
+        }
+
+        backups.push(BackupInfo {
+            created: created(&name, &metadata),
+            size: metadata.len(),
+            name,
+        });
+    }
+
+    backups.extend(Repo::new(backup_dir).list()?);
+    backups.sort_by(|a, b| b.created.cmp(&a.created).then(b.name.cmp(&a.name)));
+    Ok(backups)
+}
+
+// only plain names of existing backups are accepted, so nothing outside the directory is touched.
+// for snapshots this is the path of the snapshot file in the repository
+pub fn path(backup_dir: &Path, name: &str) -> Result<PathBuf, CommandError> {
+    let path = if dedup::is_snapshot(name) {
+        Repo::new(backup_dir).snapshot_path(name)
+    } else {
+        backup_dir.join(name)
+    };
+    let valid = (name.ends_with(EXTENSION) || dedup::is_snapshot(name))
+        && Path::new(name)
+            .file_name()
+            .map(|n| n == name)
+            .unwrap_or(false)
+        && path.is_file();
+
+    if valid {
+        Ok(path)
+    } else {
+        Err(CommandError::NonExistentBackup(name.to_string()))
+    }
+}
+
+pub fn server_backups(
+    state: &GlobalState,
+    id: usize,
+) -> Result<(PathBuf, Option<RetentionConfig>), CommandError> {
+    let lock = state.read()?;
+    let server = lock
+        .servers
+        .get(id)
+        .ok_or(CommandError::NonExistentServer(id))?;
+    let config = &server.data.config;
+
+    Ok((
+        backup_dir(Path::new(&config.dir), &config.backup),
+        config.backup.retention.clone(),
+    ))
+}
+
+pub fn list_backups(state: &GlobalState, id: usize) -> CommandResult {
+    let (backup_dir, _) = server_backups(state, id)?;
+    Ok(CommandResponse::Backups(id, list(&backup_dir)?))
+}
+
+fn delete(backup_dir: &Path, name: &str) -> io::Result<()> {
+    remove(backup_dir, name)?;
+    if dedup::is_snapshot(name) {
+        Repo::new(backup_dir).gc()?;
+    }
+    Ok(())
+}
+
+pub async fn delete_backup(state: GlobalState, id: usize, name: String) -> CommandResult {
+    let (backup_dir, _) = server_backups(&state, id)?;
+    path(&backup_dir, &name)?;
+    claim(&mut state.write()?.servers[id], id)?;
+
+    let dir = backup_dir.clone();
+    let res = tokio::task::spawn_blocking(move || delete(&dir, &name).map(|_| name)).await;
+    release(&state, id);
+
+    let name = res.map_err(|e| CommandError::BackupFailed(e.to_string()))??;
+    info!("deleted backup {} of server {}", name, id);
+    Ok(CommandResponse::Backups(id, list(&backup_dir)?))
+}
+
+fn prune(backup_dir: &Path, cfg: &RetentionConfig) -> io::Result<()> {
+    let backups = list(backup_dir)?;
+    let mut snapshots = false;
+    for backup in retention::prune(&backups, cfg) {
+        info!("pruning backup {}", backup.name);
+        remove(backup_dir, &backup.name)?;
+        snapshots |= dedup::is_snapshot(&backup.name);
+    }
+
+    if snapshots {
+        Repo::new(backup_dir).gc()?;
+    }
+    Ok(())
+}
+
+fn verify(backup_dir: &Path) -> io::Result<Vec<BackupVerification>> {
+    let mut results = vec![];
+    for backup in list(backup_dir)? {
+        if dedup::is_snapshot(&backup.name) {
+            continue;
+        }
+
+        let problems = match verify_archive(&backup_dir.join(&backup.name)) {
+            Ok(problems) => problems,
+            Err(e) => vec![format!("failed to read archive: {}", e)],
+        };
+        results.push(BackupVerification {
+            name: backup.name,
+            problems,
+        });
+    }
+
+    results.extend(Repo::new(backup_dir).verify()?);
+    Ok(results)
+}
+
+pub async fn verify_backups(state: GlobalState, id: usize) -> CommandResult {
+    let (backup_dir, _) = server_backups(&state, id)?;
+    claim(&mut state.write()?.servers[id], id)?;
+
+    let res = tokio::task::spawn_blocking(move || verify(&backup_dir)).await;
+    release(&state, id);
+
+    let results = res.map_err(|e| CommandError::BackupFailed(e.to_string()))??;
+    for result in results.iter().filter(|r| !r.problems.is_empty()) {
+        warn!(
+            "backup {} of server {} is damaged: {}",
+            result.name,
+            id,
+            result.problems.join(", ")
+        );
+    }
+
+    Ok(CommandResponse::BackupVerification(id, results))
+}
+
+async fn store(state: &GlobalState, id: usize, job: &Job) -> Result<BackupInfo, CommandError> {
+    progress(state, id, BackupProgress::Archiving);
+
+    let (dir, backup_dir, worlds) = (job.dir.clone(), job.backup_dir.clone(), job.worlds.clone());
+    let mode = job.mode;
+    tokio::task::spawn_blocking(move || match mode {
+        BackupMode::Archive => archive(&dir, &backup_dir, &worlds),
+        BackupMode::Dedup => Repo::new(&backup_dir).snapshot(&dir, &backup_dir, &worlds),
+    })
+    .await
+    .map_err(|e| CommandError::BackupFailed(e.to_string()))
+    .and_then(|res| res.map_err(|e| CommandError::BackupFailed(e.to_string())))
+}
+
+async fn run(state: &GlobalState, id: usize, job: &mut Job) -> Result<BackupInfo, CommandError> {
+    let console_rx = match job.console.as_mut() {
+        Some(console_rx) => console_rx,
+        None => return store(state, id, job).await,
+    };
+
+    progress(state, id, BackupProgress::Saving);
+    console(state, id, "save-off")?;
+
+    // saving is turned back on however the backup went
+    let res = match save(state, id, console_rx).await {
+        Ok(_) => store(state, id, job).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = console(state, id, "save-on") {
+        warn!("failed to turn saving back on: {}", e);
+    }
+
+    res
+}
+
+pub async fn backup(state: GlobalState, id: usize) -> CommandResult {
+    let mut job = begin(&state, id)?;
+    let res = run(&state, id, &mut job).await;
+
+    // pruned before finishing so nothing else can use the backup directory until it's done
+    if let (Ok(_), Ok((backup_dir, Some(cfg)))) = (&res, server_backups(&state, id)) {
+        let pruned = tokio::task::spawn_blocking(move || prune(&backup_dir, &cfg)).await;
+        if let Ok(Err(e)) = pruned {
+            error!("failed to prune backups: {}", e);
+        }
+    }
+    finish(&state, id, &res);
+
+    res.map(|info| CommandResponse::Backup(id, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    // a server directory that's removed once the test is done
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "barista-backup-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("world")).unwrap();
+            fs::write(dir.join("world/level.dat"), b"level").unwrap();
+            fs::write(dir.join("server.properties"), b"motd=hello").unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn entries(path: &Path) -> BTreeSet<String> {
+        let decoder = zstd::Decoder::new(File::open(path).unwrap()).unwrap();
+        tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .map(|p| p.trim_end_matches('/').to_string())
+            .collect()
+    }
+
+    fn names(dir: &Path) -> BTreeSet<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn leaves_out_nested_backup_dirs() {
+        let dir = TempDir::new("nested");
+        let backup_dir = dir.0.join("data").join("backups");
+        fs::create_dir_all(&backup_dir).unwrap();
+        fs::write(dir.0.join("data/players.dat"), b"players").unwrap();
+        fs::write(backup_dir.join("old.tar.zst"), b"old").unwrap();
+
+        let info = archive(&dir.0, &backup_dir, &[]).unwrap();
+        let path = backup_dir.join(&info.name);
+        let expected: BTreeSet<_> = [
+            "data",
+            "data/players.dat",
+            "server.properties",
+            "world",
+            "world/level.dat",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(entries(&path), expected);
+        assert_eq!(
+            expected_checksum(&path).unwrap(),
+            Some(checksum(&path).unwrap())
+        );
+    }
+
+    #[test]
+    fn cleans_up_after_a_failed_backup() {
+        let dir = TempDir::new("failed");
+        let backup_dir = dir.0.join("backups");
+        fs::create_dir_all(&backup_dir).unwrap();
+
+        // the checksum can't be written where a directory is in the way, a few seconds are
+        // covered in case the clock ticks over before the backup starts
+        let now = Utc::now();
+        let mut blocked = BTreeSet::new();
+        for secs in 0..3 {
+            let created = now + chrono::Duration::seconds(secs);
+            let name = unique_name(&backup_dir, &created, EXTENSION);
+            let sidecar = checksum_path(&backup_dir.join(name));
+            fs::create_dir(&sidecar).unwrap();
+            blocked.insert(sidecar.file_name().unwrap().to_string_lossy().to_string());
+        }
+
+        assert!(archive(&dir.0, &backup_dir, &[]).is_err());
+        assert_eq!(names(&backup_dir), blocked);
+    }
+}
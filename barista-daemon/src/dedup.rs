@@ -0,0 +1,686 @@
+use super::backup;
+use barista::backup::{BackupInfo, BackupVerification};
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+// region files are rewritten in place a few sectors at a time, so fixed size chunks line up
+// between snapshots and only the parts that changed are stored again
+static CHUNK_SIZE: u64 = 128 * 1024;
+static ZSTD_LEVEL: i32 = 3;
+static FORMAT_VERSION: u32 = 1;
+pub static EXTENSION: &str = ".snapshot";
+
+// the first line of a snapshot, followed by one `Entry` per line
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    created: i64,
+    // the size of every file in the snapshot, chunks are shared so the repository is usually
+    // much smaller than this
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Entry {
+    Dir {
+        path: String,
+    },
+    File {
+        path: String,
+        size: u64,
+        chunks: Vec<String>,
+    },
+}
+
+pub fn is_snapshot(name: &str) -> bool {
+    name.ends_with(EXTENSION)
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// snapshots store paths with `/` no matter the platform
+fn relative(dir: &Path, path: &Path) -> io::Result<String> {
+    let parts = path
+        .strip_prefix(dir)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid(format!("{} isn't valid unicode", path.display())))?;
+
+    Ok(parts.join("/"))
+}
+
+// paths come from a file on disk, so anything that could escape the target is refused
+fn target(dir: &Path, path: &str) -> io::Result<PathBuf> {
+    let path = Path::new(path);
+    if path.components().all(|c| matches!(c, Component::Normal(_))) {
+        Ok(dir.join(path))
+    } else {
+        Err(invalid(format!("invalid path {}", path.display())))
+    }
+}
+
+fn write_line<W: Write, T: Serialize>(out: &mut W, value: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, value).map_err(invalid)?;
+    out.write_all(b"\n")
+}
+
+pub struct Repo {
+    root: PathBuf,
+}
+
+impl Repo {
+    pub fn new(backup_dir: &Path) -> Self {
+        Self {
+            root: backup_dir.join("repo"),
+        }
+    }
+
+    fn chunks(&self) -> PathBuf {
+        self.root.join("chunks")
+    }
+
+    fn snapshots(&self) -> PathBuf {
+        self.root.join("snapshots")
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.chunks().join(&hash[..2]).join(hash)
+    }
+
+    pub fn snapshot_path(&self, name: &str) -> PathBuf {
+        self.snapshots().join(name)
+    }
+
+    // chunks are named after the hash of their contents, so one that already exists is reused
+    fn put(&self, data: &[u8]) -> io::Result<String> {
+        let hash = hash(data);
+        let path = self.chunk_path(&hash);
+        if path.is_file() {
+            return Ok(hash);
+        }
+
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, zstd::encode_all(data, ZSTD_LEVEL)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> io::Result<Vec<u8>> {
+        if hash.len() < 2 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid(format!("invalid chunk name {}", hash)));
+        }
+
+        let data = zstd::decode_all(File::open(self.chunk_path(hash))?)?;
+        if self::hash(&data) != hash {
+            return Err(invalid(format!("chunk {} is corrupt", hash)));
+        }
+        Ok(data)
+    }
+
+    fn add_file(&self, path: &Path) -> io::Result<(u64, Vec<String>)> {
+        let mut file = File::open(path)?;
+        let mut buf = Vec::with_capacity(CHUNK_SIZE as usize);
+        let mut chunks = vec![];
+        let mut size = 0;
+
+        loop {
+            buf.clear();
+            (&mut file).take(CHUNK_SIZE).read_to_end(&mut buf)?;
+            if buf.is_empty() {
+                break;
+            }
+            size += buf.len() as u64;
+            chunks.push(self.put(&buf)?);
+        }
+
+        Ok((size, chunks))
+    }
+
+    // the backup directory is skipped at any depth, it holds the repository itself
+    fn add<W: Write>(
+        &self,
+        dir: &Path,
+        path: &Path,
+        skip: &Path,
+        out: &mut W,
+        size: &mut u64,
+    ) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        let rel = relative(dir, path)?;
+
+        if metadata.is_dir() {
+            if path.canonicalize()? == skip {
+                return Ok(());
+            }
+            write_line(out, &Entry::Dir { path: rel })?;
+
+            let mut children = fs::read_dir(path)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            children.sort();
+            for child in children.iter() {
+                self.add(dir, child, skip, out, size)?;
+            }
+        } else if metadata.is_file() {
+            let (file_size, chunks) = self.add_file(path)?;
+            *size += file_size;
+            write_line(
+                out,
+                &Entry::File {
+                    path: rel,
+                    size: file_size,
+                    chunks,
+                },
+            )?;
+        }
+        // symlinks and special files are left out
+
+        Ok(())
+    }
+
+    // the entries go to a temporary file first since the size is only known at the end
+    fn write_snapshot(
+        &self,
+        dir: &Path,
+        backup_dir: &Path,
+        worlds: &[String],
+        entries: &Path,
+    ) -> io::Result<u64> {
+        let mut out = BufWriter::new(File::create(entries)?);
+        let mut size = 0;
+
+        let skip = backup_dir.canonicalize()?;
+        if worlds.is_empty() {
+            let mut children = fs::read_dir(dir)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            children.sort();
+            for path in children.iter() {
+                self.add(dir, path, &skip, &mut out, &mut size)?;
+            }
+        } else {
+            for world in worlds.iter() {
+                self.add(dir, &dir.join(world), &skip, &mut out, &mut size)?;
+            }
+        }
+
+        out.into_inner()?.sync_all()?;
+        Ok(size)
+    }
+
+    pub fn snapshot(
+        &self,
+        dir: &Path,
+        backup_dir: &Path,
+        worlds: &[String],
+    ) -> io::Result<BackupInfo> {
+        fs::create_dir_all(self.snapshots())?;
+
+        let created = Utc::now();
+        let name = backup::unique_name(&self.snapshots(), &created, EXTENSION);
+        let path = self.snapshot_path(&name);
+        let entries = self.snapshots().join(format!("{}.entries", name));
+        let partial = self.snapshots().join(format!("{}.partial", name));
+
+        let res = (|| {
+            let size = self.write_snapshot(dir, backup_dir, worlds, &entries)?;
+            let header = Header {
+                version: FORMAT_VERSION,
+                created: created.timestamp(),
+                size,
+            };
+
+            let mut out = BufWriter::new(File::create(&partial)?);
+            write_line(&mut out, &header)?;
+            io::copy(&mut File::open(&entries)?, &mut out)?;
+            out.into_inner()?.sync_all()?;
+            Ok(size)
+        })();
+
+        let _ = fs::remove_file(&entries);
+        let size = match res {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                return Err(e);
+            }
+        };
+
+        // only written once every chunk it uses is, so a snapshot is never missing any
+        fs::rename(&partial, &path)?;
+        Ok(BackupInfo {
+            name,
+            size,
+            created: created.timestamp(),
+        })
+    }
+
+    fn open(&self, name: &str) -> io::Result<(Header, io::Lines<BufReader<File>>)> {
+        let mut lines = BufReader::new(File::open(self.snapshot_path(name))?).lines();
+        let first = lines
+            .next()
+            .unwrap_or_else(|| Err(invalid("empty snapshot")))?;
+        let header: Header = serde_json::from_str(&first).map_err(invalid)?;
+
+        if header.version != FORMAT_VERSION {
+            return Err(invalid(format!(
+                "unsupported snapshot version {}",
+                header.version
+            )));
+        }
+        Ok((header, lines))
+    }
+
+    fn entries(&self, name: &str) -> io::Result<impl Iterator<Item = io::Result<Entry>>> {
+        let (_, lines) = self.open(name)?;
+        Ok(lines.map(|line| serde_json::from_str(&line?).map_err(invalid)))
+    }
+
+    fn names(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.snapshots()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut names = vec![];
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if is_snapshot(&name) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    pub fn list(&self) -> io::Result<Vec<BackupInfo>> {
+        let mut snapshots = vec![];
+        for name in self.names()? {
+            match self.open(&name) {
+                Ok((header, _)) => snapshots.push(BackupInfo {
+                    name,
+                    size: header.size,
+                    created: header.created,
+                }),
+                Err(e) => warn!("failed to read snapshot {}: {}", name, e),
+            }
+        }
+        Ok(snapshots)
+    }
+
+    // leaves the chunks behind, they're removed by `gc` once nothing uses them
+    pub fn remove(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.snapshot_path(name))
+    }
+
+    // must not run during a backup, the chunks it's written aren't used by any snapshot yet
+    pub fn gc(&self) -> io::Result<()> {
+        let mut used = HashSet::new();
+        for name in self.names()? {
+            // a snapshot that can't be read could still use any chunk, so nothing is removed
+            for entry in self.entries(&name)? {
+                if let Entry::File { chunks, .. } = entry? {
+                    used.extend(chunks);
+                }
+            }
+        }
+
+        let dirs = match fs::read_dir(self.chunks()) {
+            Ok(dirs) => dirs,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let (mut removed, mut freed) = (0, 0);
+        for dir in dirs {
+            let dir = dir?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+
+            for chunk in fs::read_dir(&dir)? {
+                let chunk = chunk?;
+                if !used.contains(&*chunk.file_name().to_string_lossy()) {
+                    freed += chunk.metadata()?.len();
+                    fs::remove_file(chunk.path())?;
+                    removed += 1;
+                }
+            }
+        }
+
+        if removed > 0 {
+            info!("removed {} unused chunks, freeing {} bytes", removed, freed);
+        }
+        Ok(())
+    }
+
+    // shared chunks are only checked once, `checked` holds the size of each one or what's wrong
+    fn verify_snapshot(
+        &self,
+        name: &str,
+        checked: &mut HashMap<String, Result<u64, String>>,
+    ) -> io::Result<Vec<String>> {
+        let mut problems = vec![];
+
+        for entry in self.entries(name)? {
+            let (path, size, chunks) = match entry? {
+                Entry::File { path, size, chunks } => (path, size, chunks),
+                Entry::Dir { .. } => continue,
+            };
+
+            let mut actual = Some(0);
+            for hash in chunks.iter() {
+                let res = checked
+                    .entry(hash.clone())
+                    .or_insert_with(|| match self.get(hash) {
+                        Ok(data) => Ok(data.len() as u64),
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                            Err(format!("chunk {} is missing", hash))
+                        }
+                        Err(e) => Err(e.to_string()),
+                    });
+
+                match res {
+                    Ok(len) => actual = actual.map(|n| n + *len),
+                    Err(e) => {
+                        problems.push(format!("{}: {}", path, e));
+                        actual = None;
+                        break;
+                    }
+                }
+            }
+
+            match actual {
+                Some(actual) if actual != size => problems.push(format!(
+                    "{}: expected {} bytes but the chunks have {}",
+                    path, size, actual
+                )),
+                _ => {}
+            }
+        }
+
+        Ok(problems)
+    }
+
+    pub fn verify(&self) -> io::Result<Vec<BackupVerification>> {
+        let mut checked = HashMap::new();
+        let mut results = vec![];
+
+        for name in self.names()? {
+            let problems = match self.verify_snapshot(&name, &mut checked) {
+                Ok(problems) => problems,
+                Err(e) => vec![format!("failed to read snapshot: {}", e)],
+            };
+            results.push(BackupVerification { name, problems });
+        }
+
+        Ok(results)
+    }
+
+    // every chunk is checked against its hash while it's read
+    pub fn restore(&self, name: &str, staging: &Path) -> io::Result<()> {
+        fs::create_dir_all(staging)?;
+
+        for entry in self.entries(name)? {
+            match entry? {
+                Entry::Dir { path } => fs::create_dir_all(target(staging, &path)?)?,
+                Entry::File { path, chunks, .. } => {
+                    let path = target(staging, &path)?;
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+
+                    let mut out = BufWriter::new(File::create(&path)?);
+                    for hash in chunks.iter() {
+                        out.write_all(&self.get(hash)?)?;
+                    }
+                    out.flush()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    // a server directory with its backups inside it, removed once the test is done
+    struct Server {
+        root: PathBuf,
+        dir: PathBuf,
+        backup_dir: PathBuf,
+    }
+
+    impl Server {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("barista-dedup-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&root);
+            let dir = root.join("server");
+            let backup_dir = dir.join("backups");
+
+            fs::create_dir_all(dir.join("world").join("region")).unwrap();
+            fs::create_dir_all(&backup_dir).unwrap();
+            // big enough to be split over a few chunks
+            let region: Vec<u8> = (0..300 * 1024).map(|i| (i * 31 % 251) as u8).collect();
+            fs::write(dir.join("world/region/r.0.0.mca"), region).unwrap();
+            fs::write(dir.join("world/level.dat"), b"level").unwrap();
+            fs::write(dir.join("server.properties"), b"motd=first").unwrap();
+
+            Self {
+                root,
+                dir,
+                backup_dir,
+            }
+        }
+
+        fn repo(&self) -> Repo {
+            Repo::new(&self.backup_dir)
+        }
+
+        fn snapshot(&self) -> BackupInfo {
+            self.repo()
+                .snapshot(&self.dir, &self.backup_dir, &[])
+                .unwrap()
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn files(dir: &Path, out: &mut BTreeMap<String, Vec<u8>>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files(&path, out);
+            } else {
+                out.insert(path.display().to_string(), fs::read(&path).unwrap());
+            }
+        }
+    }
+
+    fn contents(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+        let mut all = BTreeMap::new();
+        files(dir, &mut all);
+        all.into_iter()
+            .map(|(path, data)| (relative(dir, Path::new(&path)).unwrap(), data))
+            .collect()
+    }
+
+    fn chunks(repo: &Repo) -> Vec<String> {
+        contents(&repo.chunks()).into_keys().collect()
+    }
+
+    #[test]
+    fn restores_what_was_snapshotted() {
+        let server = Server::new("round-trip");
+        let info = server.snapshot();
+
+        let staging = server.root.join("staging");
+        server.repo().restore(&info.name, &staging).unwrap();
+
+        let mut expected = contents(&server.dir);
+        expected.retain(|path, _| !path.starts_with("backups/"));
+        assert_eq!(contents(&staging), expected);
+        assert_eq!(info.size, 300 * 1024 + 5 + 10);
+    }
+
+    #[test]
+    fn leaves_out_nested_backup_dirs() {
+        let server = Server::new("nested");
+        let backup_dir = server.dir.join("world").join("backups");
+        fs::create_dir_all(&backup_dir).unwrap();
+        fs::write(backup_dir.join("old.tar.zst"), b"old").unwrap();
+
+        let repo = Repo::new(&backup_dir);
+        let info = repo.snapshot(&server.dir, &backup_dir, &[]).unwrap();
+        let staging = server.root.join("staging");
+        repo.restore(&info.name, &staging).unwrap();
+
+        let restored = contents(&staging);
+        assert!(restored.contains_key("world/level.dat"));
+        assert!(!restored.keys().any(|p| p.starts_with("world/backups")));
+    }
+
+    #[test]
+    fn reuses_existing_chunks() {
+        let server = Server::new("reuse");
+        let repo = server.repo();
+        server.snapshot();
+        let before = chunks(&repo);
+        // three for the region file, plus one each for the other files
+        assert_eq!(before.len(), 5);
+
+        fs::write(server.dir.join("server.properties"), b"motd=second").unwrap();
+        server.snapshot();
+        let after = chunks(&repo);
+        assert_eq!(after.len(), before.len() + 1);
+        assert!(before.iter().all(|c| after.contains(c)));
+    }
+
+    #[test]
+    fn gc_removes_unused_chunks() {
+        let server = Server::new("gc");
+        let repo = server.repo();
+        let first = server.snapshot();
+        let old = chunks(&repo);
+
+        fs::write(server.dir.join("server.properties"), b"motd=second").unwrap();
+        let second = server.snapshot();
+
+        repo.gc().unwrap();
+        assert_eq!(chunks(&repo).len(), old.len() + 1);
+
+        repo.remove(&first.name).unwrap();
+        repo.gc().unwrap();
+        let left = chunks(&repo);
+        assert_eq!(left.len(), old.len());
+        let properties = hash(b"motd=first");
+        assert!(!left.iter().any(|c| c.ends_with(&properties)));
+
+        let staging = server.root.join("staging");
+        repo.restore(&second.name, &staging).unwrap();
+        assert_eq!(
+            fs::read(staging.join("server.properties")).unwrap(),
+            b"motd=second"
+        );
+    }
+
+    #[test]
+    fn verify_finds_corrupt_chunks() {
+        let server = Server::new("verify");
+        let repo = server.repo();
+        let info = server.snapshot();
+
+        let results = repo.verify().unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].problems.is_empty());
+
+        let level = repo.chunk_path(&hash(b"level"));
+        fs::write(
+            &level,
+            zstd::encode_all(&b"corrupt"[..], ZSTD_LEVEL).unwrap(),
+        )
+        .unwrap();
+
+        let results = repo.verify().unwrap();
+        assert_eq!(results[0].name, info.name);
+        assert_eq!(results[0].problems.len(), 1);
+        assert!(results[0].problems[0].starts_with("world/level.dat: "));
+        assert!(repo
+            .restore(&info.name, &server.root.join("staging"))
+            .is_err());
+    }
+
+    #[test]
+    fn restores_inside_the_target() {
+        let dir = Path::new("/srv/survival");
+        assert_eq!(
+            target(dir, "world/region/r.0.0.mca").unwrap(),
+            dir.join("world").join("region").join("r.0.0.mca")
+        );
+        assert_eq!(
+            target(dir, "server.properties").unwrap(),
+            dir.join("server.properties")
+        );
+        // the directory itself is stored with an empty path
+        assert_eq!(target(dir, "").unwrap(), dir.join(""));
+    }
+
+    #[test]
+    fn refuses_paths_outside_the_target() {
+        let dir = Path::new("/srv/survival");
+        for path in &["../other/world", "world/../../etc", "/etc/passwd", "."] {
+            assert!(target(dir, path).is_err(), "{} was allowed", path);
+        }
+    }
+
+    #[test]
+    fn stores_relative_paths() {
+        let dir = Path::new("/srv/survival");
+        assert_eq!(
+            relative(dir, &dir.join("world").join("level.dat")).unwrap(),
+            "world/level.dat"
+        );
+        assert_eq!(relative(dir, dir).unwrap(), "");
+    }
+
+    #[test]
+    fn spreads_chunks_over_directories() {
+        let repo = Repo::new(Path::new("/backups"));
+        let hash = hash(b"chunk");
+        assert_eq!(
+            repo.chunk_path(&hash),
+            Path::new("/backups/repo/chunks")
+                .join(&hash[..2])
+                .join(&hash)
+        );
+        assert_eq!(
+            repo.snapshot_path("1.snapshot"),
+            Path::new("/backups/repo/snapshots/1.snapshot")
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use super::properties::Properties;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static EULA_URL: &str = "https://account.mojang.com/documents/minecraft_eula";
+
+fn path(dir: &Path) -> PathBuf {
+    dir.join("eula.txt")
+}
+
+pub fn accepted(dir: &Path) -> io::Result<bool> {
+    let eula = Properties::open(path(dir))?;
+    Ok(matches!(eula.get("eula"), Some(v) if v.eq_ignore_ascii_case("true")))
+}
+
+// writes the same file the server would, with the setting flipped
+pub fn accept(dir: &Path) -> io::Result<()> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let contents = format!(
+        "#By changing the setting below to TRUE you are indicating your agreement to our EULA ({}).\n#accepted through mined at {}\neula=true\n",
+        EULA_URL, secs
+    );
+
+    std::fs::write(path(dir), contents)
+}
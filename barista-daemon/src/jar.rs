@@ -0,0 +1,175 @@
+use log::warn;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+// class files count from 45 for java 1.1, so java 8 is 52
+static CLASS_VERSION_OFFSET: u16 = 44;
+static CLASS_MAGIC: [u8; 4] = [0xca, 0xfe, 0xba, 0xbe];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JarInfo {
+    pub minecraft_version: Option<String>,
+    pub required_java: Option<u32>,
+}
+
+// vanilla jars have this at the root, `java_version` was added in 1.17
+#[derive(Deserialize)]
+struct VersionJson {
+    id: Option<String>,
+    name: Option<String>,
+    java_version: Option<u32>,
+}
+
+fn read_entry(zip: &mut ZipArchive<File>, name: &str) -> Result<Option<Vec<u8>>, ZipError> {
+    let mut entry = match zip.by_name(name) {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut buf = vec![];
+    entry.read_to_end(&mut buf)?;
+    Ok(Some(buf))
+}
+
+// lines are wrapped at 72 bytes, the rest of a value is on lines starting with a space
+fn manifest_value(manifest: &str, key: &str) -> Option<String> {
+    let mut value: Option<String> = None;
+
+    for line in manifest.lines() {
+        if let Some(v) = value.as_mut() {
+            match line.strip_prefix(' ') {
+                Some(rest) => v.push_str(rest),
+                None => break,
+            }
+        } else if let Some((k, v)) = line.split_once(':') {
+            if k == key {
+                value = Some(v.trim_start().to_string());
+            }
+        }
+    }
+
+    value
+}
+
+// paper and spigot put it in the implementation version, like `git-Paper-794 (MC: 1.16.5)`
+fn implementation_minecraft_version(version: &str) -> Option<String> {
+    let start = version.find("(MC: ")? + "(MC: ".len();
+    let end = start + version[start..].find(')')?;
+    Some(version[start..end].trim().to_string())
+}
+
+fn class_java_version(class: &[u8]) -> Option<u32> {
+    if class.len() < 8 || class[..4] != CLASS_MAGIC {
+        return None;
+    }
+
+    let major = u16::from_be_bytes([class[6], class[7]]);
+    major.checked_sub(CLASS_VERSION_OFFSET).map(u32::from)
+}
+
+// anything that can't be found is left out, modded jars often have neither
+pub fn inspect(jar: &Path) -> Result<JarInfo, ZipError> {
+    let mut zip = ZipArchive::new(File::open(jar)?)?;
+    let mut info = JarInfo::default();
+
+    if let Some(data) = read_entry(&mut zip, "version.json")? {
+        if let Ok(version) = serde_json::from_slice::<VersionJson>(&data) {
+            // older versions have the hash in the id as well
+            info.minecraft_version = version.name.or(version.id);
+            info.required_java = version.java_version;
+        }
+    }
+
+    let manifest = match read_entry(&mut zip, "META-INF/MANIFEST.MF")? {
+        Some(manifest) => String::from_utf8_lossy(&manifest).into_owned(),
+        None => return Ok(info),
+    };
+
+    if info.minecraft_version.is_none() {
+        info.minecraft_version = manifest_value(&manifest, "Implementation-Version")
+            .and_then(|v| implementation_minecraft_version(&v));
+    }
+
+    // the main class can't run on anything older than it was compiled for
+    if info.required_java.is_none() {
+        if let Some(main) = manifest_value(&manifest, "Main-Class") {
+            let class = format!("{}.class", main.replace('.', "/"));
+            info.required_java = read_entry(&mut zip, &class)?.and_then(|c| class_java_version(&c));
+        }
+    }
+
+    Ok(info)
+}
+
+// a missing jar is left to preflight to complain about
+pub fn read(jar: &Path) -> JarInfo {
+    if !jar.is_file() {
+        return JarInfo::default();
+    }
+
+    inspect(jar).unwrap_or_else(|e| {
+        warn!("failed to read {}: {}", jar.display(), e);
+        JarInfo::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_manifest_values() {
+        let manifest = "Manifest-Version: 1.0\r\n\
+                        Main-Class: org.bukkit.craftbukkit.Main\r\n\
+                        Implementation-Version: git-Paper-794 (MC: 1.16.5)\r\n";
+        assert_eq!(
+            manifest_value(manifest, "Main-Class").as_deref(),
+            Some("org.bukkit.craftbukkit.Main")
+        );
+        assert_eq!(
+            manifest_value(manifest, "Implementation-Version").as_deref(),
+            Some("git-Paper-794 (MC: 1.16.5)")
+        );
+        assert_eq!(manifest_value(manifest, "Class-Path"), None);
+    }
+
+    #[test]
+    fn joins_wrapped_manifest_values() {
+        let manifest =
+            "Main-Class: net.minecraft.server.dedicated.Dedica\n tedServerMain\nBuild-Jdk: 16\n";
+        assert_eq!(
+            manifest_value(manifest, "Main-Class").as_deref(),
+            Some("net.minecraft.server.dedicated.DedicatedServerMain")
+        );
+    }
+
+    #[test]
+    fn reads_minecraft_version_from_implementation() {
+        assert_eq!(
+            implementation_minecraft_version("git-Paper-794 (MC: 1.16.5)").as_deref(),
+            Some("1.16.5")
+        );
+        assert_eq!(implementation_minecraft_version("1.0-SNAPSHOT"), None);
+    }
+
+    #[test]
+    fn reads_class_java_version() {
+        let class = |major: u16| {
+            let mut class = CLASS_MAGIC.to_vec();
+            class.extend_from_slice(&0u16.to_be_bytes());
+            class.extend_from_slice(&major.to_be_bytes());
+            class
+        };
+
+        assert_eq!(class_java_version(&class(52)), Some(8));
+        assert_eq!(class_java_version(&class(60)), Some(16));
+        assert_eq!(class_java_version(&class(20)), None);
+        assert_eq!(class_java_version(&class(52)[..6]), None);
+        assert_eq!(class_java_version(&[0; 8]), None);
+    }
+}
@@ -0,0 +1,254 @@
+use barista::java::{major_version, JavaRuntime};
+use barista::preflight::PreflightError;
+use log::info;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub static DEFAULT_JAVA: &str = "java";
+
+#[cfg(not(windows))]
+static JAVA_BINARY: &str = "bin/java";
+#[cfg(windows)]
+static JAVA_BINARY: &str = "bin\\java.exe";
+
+#[cfg(not(windows))]
+static SEARCH_DIRS: &[&str] = &[
+    "/usr/lib/jvm",
+    "/usr/lib64/jvm",
+    "/usr/java",
+    "/opt/java",
+    "/opt/jdk",
+    "/Library/Java/JavaVirtualMachines",
+];
+#[cfg(windows)]
+static SEARCH_DIRS: &[&str] = &[
+    "C:\\Program Files\\Java",
+    "C:\\Program Files\\Eclipse Adoptium",
+    "C:\\Program Files\\Microsoft",
+    "C:\\Program Files\\Zulu",
+];
+
+// inside the home directory, where sdkman and intellij put them
+#[cfg(not(windows))]
+static HOME_SEARCH_DIRS: &[&str] = &[".sdkman/candidates/java", ".jdks"];
+#[cfg(windows)]
+static HOME_SEARCH_DIRS: &[&str] = &[".jdks"];
+
+// the major version from `java -version`, which looks like `openjdk version "1.8.0_275"` before
+// java 9 and `openjdk version "17.0.1" 2021-10-19` after
+pub fn parse_version(output: &str) -> Option<u32> {
+    let line = output.lines().find(|l| l.contains(" version \""))?;
+    major_version(line.split('"').nth(1)?)
+}
+
+// None if it ran but the output couldn't be understood
+pub fn version(java: &str) -> io::Result<Option<u32>> {
+    let output = Command::new(java).arg("-version").output()?;
+    Ok(parse_version(&String::from_utf8_lossy(&output.stderr)))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    #[cfg(not(windows))]
+    let var = "HOME";
+    #[cfg(windows)]
+    let var = "USERPROFILE";
+
+    env::var_os(var).map(PathBuf::from)
+}
+
+// the `release` file has lines like `JAVA_VERSION="17.0.1"`
+fn parse_release(contents: &str, key: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let (k, v) = line.split_once('=')?;
+        if k.trim() == key {
+            Some(v.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn runtime(home: &Path) -> Option<JavaRuntime> {
+    // macos keeps the actual home inside the bundle
+    let bundled = home.join("Contents").join("Home");
+    let home = if bundled.is_dir() { &bundled } else { home };
+
+    let release = fs::read_to_string(home.join("release")).ok()?;
+    let version = parse_release(&release, "JAVA_VERSION")?;
+    let major = major_version(&version)?;
+
+    let java = home.join(JAVA_BINARY);
+    if !java.is_file() {
+        return None;
+    }
+    // symlinks like default-java would list the same runtime twice
+    let java = fs::canonicalize(&java).unwrap_or(java);
+
+    Some(JavaRuntime {
+        path: java.display().to_string(),
+        version,
+        major,
+        vendor: parse_release(&release, "IMPLEMENTOR"),
+    })
+}
+
+// every runtime in the usual places and `dirs`, newest first
+pub fn discover(dirs: &[String]) -> Vec<JavaRuntime> {
+    let mut homes = vec![];
+    if let Some(home) = env::var_os("JAVA_HOME") {
+        homes.push(PathBuf::from(home));
+    }
+
+    let mut search: Vec<PathBuf> = SEARCH_DIRS.iter().map(PathBuf::from).collect();
+    if let Some(home) = home_dir() {
+        search.extend(HOME_SEARCH_DIRS.iter().map(|d| home.join(d)));
+    }
+    search.extend(dirs.iter().map(PathBuf::from));
+
+    for dir in search {
+        // the directory might be a runtime itself
+        homes.push(dir.clone());
+        if let Ok(entries) = fs::read_dir(&dir) {
+            homes.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+        }
+    }
+
+    let mut runtimes: Vec<JavaRuntime> = vec![];
+    for runtime in homes.iter().filter_map(|h| runtime(h)) {
+        if !runtimes.iter().any(|r| r.path == runtime.path) {
+            info!("found java {} at {}", runtime.version, runtime.path);
+            runtimes.push(runtime);
+        }
+    }
+
+    runtimes.sort_by(|a, b| b.major.cmp(&a.major).then_with(|| a.path.cmp(&b.path)));
+    runtimes
+}
+
+// the java binary a server should be started with, `required` is what its jar needs
+pub fn resolve(
+    java: Option<&str>,
+    required: Option<u32>,
+    runtimes: &[JavaRuntime],
+) -> Result<String, PreflightError> {
+    let java = match java {
+        Some(java) => java.trim(),
+        // the closest version is the least likely to break older mods
+        None => {
+            let runtime = required.and_then(|r| runtimes.iter().rev().find(|rt| rt.major >= r));
+            let java = runtime.map_or(DEFAULT_JAVA, |rt| rt.path.as_str());
+            return Ok(java.to_string());
+        }
+    };
+
+    let (major, newer) = match java.strip_suffix('+') {
+        Some(major) => (major_version(major), true),
+        None => (major_version(java), false),
+    };
+    // anything that isn't a version is a path
+    let major = match major {
+        Some(major) if !java.contains(['/', '\\']) => major,
+        _ => return Ok(java.to_string()),
+    };
+
+    runtimes
+        .iter()
+        .find(|r| r.major == major || (newer && r.major > major))
+        .map(|r| r.path.clone())
+        .ok_or_else(|| PreflightError::NoJavaRuntime(java.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_java_8_version() {
+        let output = "openjdk version \"1.8.0_275\"\n\
+                      OpenJDK Runtime Environment (build 1.8.0_275-b01)\n\
+                      OpenJDK 64-Bit Server VM (build 25.275-b01, mixed mode)\n";
+        assert_eq!(parse_version(output), Some(8));
+    }
+
+    #[test]
+    fn parses_modern_versions() {
+        let output = "openjdk version \"17.0.1\" 2021-10-19\n\
+                      OpenJDK Runtime Environment (build 17.0.1+12-39)\n";
+        assert_eq!(parse_version(output), Some(17));
+
+        let output = "java version \"16\" 2021-03-16\n";
+        assert_eq!(parse_version(output), Some(16));
+    }
+
+    #[test]
+    fn skips_lines_before_the_version() {
+        let output = "Picked up _JAVA_OPTIONS: -Dawt.useSystemAAFontSettings=on\n\
+                      openjdk version \"11.0.9\" 2020-10-20\n";
+        assert_eq!(parse_version(output), Some(11));
+    }
+
+    #[test]
+    fn rejects_unknown_output() {
+        assert_eq!(parse_version(""), None);
+        assert_eq!(parse_version("bash: java: command not found"), None);
+        assert_eq!(parse_version("openjdk version \"\""), None);
+    }
+
+    fn runtime(major: u32) -> JavaRuntime {
+        JavaRuntime {
+            path: format!("/usr/lib/jvm/java-{}/bin/java", major),
+            version: major.to_string(),
+            major,
+            vendor: None,
+        }
+    }
+
+    #[test]
+    fn reads_release_files() {
+        let release = "IMPLEMENTOR=\"Eclipse Adoptium\"\nJAVA_VERSION=\"17.0.1\"\n";
+        assert_eq!(
+            parse_release(release, "JAVA_VERSION").as_deref(),
+            Some("17.0.1")
+        );
+        assert_eq!(
+            parse_release(release, "IMPLEMENTOR").as_deref(),
+            Some("Eclipse Adoptium")
+        );
+        assert_eq!(parse_release(release, "JAVA_RUNTIME_VERSION"), None);
+    }
+
+    #[test]
+    fn resolves_configured_java() {
+        let runtimes = vec![runtime(17), runtime(11), runtime(8)];
+
+        assert_eq!(
+            resolve(Some("11"), None, &runtimes).unwrap(),
+            runtimes[1].path
+        );
+        assert_eq!(
+            resolve(Some("9+"), None, &runtimes).unwrap(),
+            runtimes[0].path
+        );
+        assert_eq!(
+            resolve(Some("/opt/java/bin/java"), None, &runtimes).unwrap(),
+            "/opt/java/bin/java"
+        );
+        assert!(matches!(
+            resolve(Some("16"), None, &runtimes),
+            Err(PreflightError::NoJavaRuntime(_))
+        ));
+    }
+
+    #[test]
+    fn resolves_closest_required_java() {
+        let runtimes = vec![runtime(17), runtime(11), runtime(8)];
+
+        assert_eq!(resolve(None, Some(9), &runtimes).unwrap(), runtimes[1].path);
+        assert_eq!(resolve(None, Some(8), &runtimes).unwrap(), runtimes[2].path);
+        assert_eq!(resolve(None, Some(21), &runtimes).unwrap(), DEFAULT_JAVA);
+        assert_eq!(resolve(None, None, &runtimes).unwrap(), DEFAULT_JAVA);
+    }
+}
@@ -1,13 +1,17 @@
 use barista::command::*;
 use barista::config::Config;
-use barista::server::ServerData;
+use barista::server::{ServerData, ServerInfo, Status};
+#[cfg(unix)]
+use barista::socket::SOCKET_PATH;
 use clap::{App, Arg};
+use futures::future::{self, BoxFuture};
 use futures::{FutureExt, StreamExt};
 use log::{error, info, trace, warn};
 use std::cmp::Ordering;
 use std::env;
 use std::path::Path;
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 use tokio::fs::File;
 use tokio::prelude::*;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
@@ -15,9 +19,40 @@ use warp::fs;
 use warp::ws::Message;
 use warp::Filter;
 
+mod access;
+mod backup;
+mod dedup;
+mod eula;
+mod jar;
+mod java;
+mod metrics;
+mod ports;
+mod preflight;
+mod properties;
+mod rcon;
+mod restore;
+mod retention;
+mod router;
+mod schedule;
 mod server;
-
+mod shutdown;
+mod slp;
+#[cfg(unix)]
+mod socket;
+mod start;
+mod sync;
+mod systemd;
+mod wake;
+mod watchdog;
+mod webhook;
+
+use metrics::Metrics;
+use schedule::Schedule;
 use server::Server;
+use slp::SlpError;
+use sync::SyncGroup;
+use systemd::ServerContext;
+use webhook::Webhooks;
 
 static WEBSITE_PATH: &str = "build/dist";
 static CONFIG_VERSION: u64 = 1;
@@ -26,21 +61,53 @@ struct State {
     servers: Vec<Server>,
     tx: UnboundedSender<Message>,
     clients: Vec<UnboundedSender<Result<Message, warp::Error>>>,
+    // connected to the unix socket, kept apart so they can be counted on their own
+    socket_clients: Vec<UnboundedSender<Result<Message, warp::Error>>>,
+    metrics: Metrics,
+    webhooks: Webhooks,
+    sync_groups: Vec<SyncGroup>,
+    // indexed by server id
+    schedules: Vec<Vec<Schedule>>,
+    java_dirs: Vec<String>,
 }
 
 impl State {
     pub fn new(config: Config, tx: UnboundedSender<Message>) -> Self {
         let mut servers = vec![];
+        let mut schedules = vec![];
         let clients = vec![];
+        let runtimes = java::discover(&config.java_dirs);
         for id in 0..config.servers.len() {
             let cfg = config.servers[id].clone();
             let data = ServerData::new(id, cfg);
-            servers.push(Server::new(data));
+            let mut server = Server::new(data);
+            let _ctx = ServerContext::enter(&server.data);
+            server.runtimes = runtimes.clone();
+            server.bootstrap();
+            schedules.push(schedule::resolve(&config.servers[id]));
+            servers.push(server);
+        }
+        if let Some(range) = &config.ports {
+            ports::assign(&servers, range, config.router.as_ref());
         }
+        ports::check_config(&servers, config.router.as_ref());
+
+        let sync_groups = config
+            .sync_groups
+            .iter()
+            .filter_map(|g| SyncGroup::resolve(g, &config.servers))
+            .collect();
+
         Self {
             servers,
             tx,
             clients,
+            socket_clients: vec![],
+            metrics: Metrics::default(),
+            webhooks: Webhooks::new(config.webhooks),
+            sync_groups,
+            schedules,
+            java_dirs: config.java_dirs,
         }
     }
 }
@@ -71,6 +138,13 @@ enum ServerError {
     InvalidConfig(serde_yaml::Error),
     InvalidConfigVersion,
     IoError(std::io::Error),
+    BindError(warp::Error),
+}
+
+impl From<warp::Error> for ServerError {
+    fn from(e: warp::Error) -> Self {
+        Self::BindError(e)
+    }
 }
 
 impl From<serde_yaml::Error> for ServerError {
@@ -91,6 +165,7 @@ impl std::fmt::Display for ServerError {
             Self::InvalidConfigVersion => "config isn't a valid version".to_string(),
             Self::InvalidConfig(e) => format!("error parsing config: {}", e),
             Self::IoError(e) => format!("io error: {}", e),
+            Self::BindError(e) => format!("failed to bind web server: {}", e),
         };
 
         write!(f, "{}", msg)
@@ -111,43 +186,170 @@ impl std::fmt::Display for WebsocketError {
 
 impl std::error::Error for WebsocketError {}
 
-fn run_command(cmd: Command, state: GlobalState) -> CommandResult {
+fn rcon_target(state: &GlobalState, id: usize) -> Result<(String, u16, String), CommandError> {
+    let lock = state.read()?;
+    lock.servers
+        .get(id)
+        .ok_or(CommandError::NonExistentServer(id))?
+        .rcon()
+}
+
+async fn run_command(cmd: Command, state: GlobalState) -> CommandResult {
     match cmd {
         Command::GetServers => {
             let lock = state.read()?;
             let server_data = lock.servers.iter().map(|s| s.data.clone()).collect();
             Ok(CommandResponse::UpdateServers(server_data))
         }
-        Command::StartServer(id) => {
+        Command::StartServer(id) => start::start(&state, id).await,
+        Command::StopServer(id, delay) => shutdown::request(state, id, delay, false).await,
+        Command::RestartServer(id, delay) => shutdown::request(state, id, delay, true).await,
+        Command::CancelShutdown(id) => shutdown::cancel(&state, id),
+        Command::Rcon(id, cmd) => {
+            let (host, port, password) = rcon_target(&state, id)?;
+            rcon::run(&host, port, &password, &cmd)
+                .await
+                .map(|res| CommandResponse::RconResponse(id, res))
+                .map_err(|e| CommandError::RconError(e.to_string()))
+        }
+        Command::GetProperties(id) => {
+            let lock = state.read()?;
+            lock.servers
+                .get(id)
+                .ok_or(CommandError::NonExistentServer(id))?
+                .get_properties()
+        }
+        Command::SetProperties(id, changes) => {
+            let mut lock = state.write()?;
+            let lock = &mut *lock;
+            let server = lock
+                .servers
+                .get_mut(id)
+                .ok_or(CommandError::NonExistentServer(id))?;
+            let _ctx = ServerContext::enter(&server.data);
+
+            let (res, restart_required) = server.set_properties(changes)?;
+            if restart_required {
+                let data = server.data.clone();
+                broadcast(&lock.tx, &CommandResponse::UpdateServer(data.id, data));
+            }
+            Ok(res)
+        }
+        Command::AcceptEula(id) => {
             let mut lock = state.write()?;
-            lock.servers[id].start()
+            let server = lock
+                .servers
+                .get_mut(id)
+                .ok_or(CommandError::NonExistentServer(id))?;
+            let _ctx = ServerContext::enter(&server.data);
+            server.accept_eula()
         }
-        Command::StopServer(id) => {
+        Command::GetAccessList(id, list) => {
+            let dir = {
+                let lock = state.read()?;
+                let server = lock
+                    .servers
+                    .get(id)
+                    .ok_or(CommandError::NonExistentServer(id))?;
+                server.data.config.dir.clone()
+            };
+            let entries = access::entries(Path::new(&dir), list)?;
+            Ok(CommandResponse::AccessList(id, list, entries))
+        }
+        Command::AddAccessEntry(id, list, name) => {
+            access::update(state, id, list, name, true).await
+        }
+        Command::RemoveAccessEntry(id, list, name) => {
+            access::update(state, id, list, name, false).await
+        }
+        Command::GetSyncDrift => Ok(CommandResponse::SyncDrift(sync::drift(&state)?)),
+        Command::Backup(id) => backup::backup(state, id).await,
+        Command::ListBackups(id) => backup::list_backups(&state, id),
+        Command::DeleteBackup(id, name) => backup::delete_backup(state, id, name).await,
+        Command::RestoreBackup(id, name) => restore::restore_backup(state, id, name).await,
+        Command::VerifyBackups(id) => backup::verify_backups(state, id).await,
+        Command::ListSchedules(id) => schedule::list_schedules(&state, id),
+        Command::RunScheduleNow(id, name) => schedule::run_now(state, id, name).await,
+        Command::EnableSchedule(id, name) => schedule::set_enabled(&state, id, &name, true),
+        Command::DisableSchedule(id, name) => schedule::set_enabled(&state, id, &name, false),
+        Command::Preflight(id) => start::preflight(&state, id).await,
+        Command::ListJavaRuntimes => {
             let mut lock = state.write()?;
-            lock.servers[id].stop()
+            let runtimes = java::discover(&lock.java_dirs);
+            for server in lock.servers.iter_mut() {
+                server.runtimes = runtimes.clone();
+            }
+            Ok(CommandResponse::JavaRuntimes(runtimes))
         }
+        cmd @ Command::Tagged(_, _) => Ok(respond(cmd, state).await),
     }
 }
 
+// boxed since tagged commands recurse
+fn respond(cmd: Command, state: GlobalState) -> BoxFuture<'static, CommandResponse> {
+    async move {
+        match cmd {
+            Command::Tagged(tag, cmd) => {
+                CommandResponse::Tagged(tag, Box::new(respond(*cmd, state).await))
+            }
+            cmd => {
+                let name = cmd.name();
+                let start = Instant::now();
+                let res = run_command(cmd, state.clone()).await;
+
+                if let Ok(mut lock) = state.write() {
+                    lock.metrics.record(name, start.elapsed(), res.is_err());
+                }
+
+                match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        error!("error running command: {}", e);
+                        CommandResponse::Error(e)
+                    }
+                }
+            }
+        }
+    }
+    .boxed()
+}
+
+fn serve_metrics(state: GlobalState) -> Box<dyn warp::Reply> {
+    let lock = match state.read() {
+        Ok(lock) => lock,
+        Err(e) => {
+            let e = CommandError::from(e);
+            error!("failed to render metrics: {}", e);
+            return Box::new(warp::reply::with_status(
+                e.to_string(),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+    };
+
+    Box::new(warp::reply::with_header(
+        metrics::render(&lock),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
 fn serialize_ws(cmd: &CommandResponse) -> Result<Message, serde_cbor::Error> {
     Ok(Message::binary(serde_cbor::to_vec(cmd)?))
 }
 
-fn serve_ws(data: Message, state: GlobalState) -> Result<Message, WebsocketError> {
+async fn serve_cmd(bytes: &[u8], state: GlobalState) -> Result<CommandResponse, serde_cbor::Error> {
+    let cmd = serde_cbor::from_slice::<Command>(bytes)?;
+
+    Ok(respond(cmd, state).await)
+}
+
+async fn serve_ws(data: Message, state: GlobalState) -> Result<Message, WebsocketError> {
     if !data.is_binary() {
         return Err(WebsocketError::NotBinary);
     }
 
-    let bytes = &data.as_bytes();
-    let cmd = serde_cbor::from_slice::<Command>(bytes)?;
-
-    let res = match run_command(cmd, state) {
-        Ok(res) => res,
-        Err(e) => {
-            error!("error running command: {}", e);
-            CommandResponse::Error(e)
-        }
-    };
+    let res = serve_cmd(data.as_bytes(), state).await?;
 
     Ok(serialize_ws(&res)?)
 }
@@ -171,7 +373,7 @@ fn handle_ws(ws: warp::ws::Ws, state: GlobalState) -> impl warp::Reply {
         while let Some(req) = ws_rx.next().await {
             match req {
                 Ok(msg) => {
-                    let response = match serve_ws(msg, state.clone()) {
+                    let response = match serve_ws(msg, state.clone()).await {
                         Ok(r) => r,
                         Err(e) => {
                             match e {
@@ -194,32 +396,158 @@ fn handle_ws(ws: warp::ws::Ws, state: GlobalState) -> impl warp::Reply {
     })
 }
 
+fn broadcast(tx: &UnboundedSender<Message>, res: &CommandResponse) {
+    match serialize_ws(res) {
+        Ok(msg) => {
+            if let Err(e) = tx.send(msg) {
+                error!("failed to update clients: {}", e);
+            }
+        }
+        Err(e) => error!("failed to serialize ws message: {}", e),
+    }
+}
+
+fn dispatch_events(webhooks: &Webhooks, server: &mut Server) {
+    for (event, player) in server.take_events() {
+        webhooks.dispatch(event, &server.data, player.as_deref());
+    }
+}
+
+// a server to ping and the address to ping it on
+type Probe = (usize, (String, u16));
+
+// returns the servers to ping and the ids of the ones waiting to be started again
+fn update_status(state: &GlobalState) -> (Vec<Probe>, Vec<usize>) {
+    let mut guard = state.write().unwrap();
+    let lock = &mut *guard;
+    let mut probes = vec![];
+    let mut restarts = vec![];
+
+    for server in lock.servers.iter_mut() {
+        let _ctx = ServerContext::enter(&server.data);
+        let changed = server.update_status();
+
+        dispatch_events(&lock.webhooks, server);
+
+        if changed {
+            let data = server.data.clone();
+            broadcast(&lock.tx, &CommandResponse::UpdateServer(data.id, data));
+        }
+
+        if let Some(addr) = server.address() {
+            probes.push((server.data.id, addr));
+        }
+
+        if server.restart_due() {
+            restarts.push(server.data.id);
+        }
+    }
+
+    (probes, restarts)
+}
+
+// returns the id and pid of every server the watchdog just found to be hung
+fn update_health(
+    state: &GlobalState,
+    results: Vec<(usize, Result<ServerInfo, SlpError>)>,
+) -> Vec<(usize, u32)> {
+    let mut guard = state.write().unwrap();
+    let lock = &mut *guard;
+    let mut hung = vec![];
+
+    for (id, res) in results {
+        let server = &mut lock.servers[id];
+        let _ctx = ServerContext::enter(&server.data);
+        let was_hung = server.data.status == Status::Unresponsive;
+
+        if server.update_health(res) {
+            let data = server.data.clone();
+            broadcast(&lock.tx, &CommandResponse::UpdateServer(data.id, data));
+        }
+
+        dispatch_events(&lock.webhooks, server);
+
+        if !was_hung && server.data.status == Status::Unresponsive {
+            if let Some(pid) = server.pid() {
+                hung.push((id, pid));
+            }
+        }
+    }
+
+    hung
+}
+
+fn restart_hung(state: &GlobalState, id: usize, pid: u32) {
+    let mut guard = state.write().unwrap();
+    let lock = &mut *guard;
+    let server = &mut lock.servers[id];
+    let _ctx = ServerContext::enter(&server.data);
+
+    // it might have recovered or been stopped while the thread dump was taken
+    if server.data.status != Status::Unresponsive || server.pid() != Some(pid) {
+        return;
+    }
+
+    warn!("restarting {}", server.data.name);
+    match server.kill_and_restart() {
+        Ok(res) => broadcast(&lock.tx, &res),
+        Err(e) => {
+            error!("failed to restart {}: {}", server.data.name, e);
+            let data = server.data.clone();
+            broadcast(&lock.tx, &CommandResponse::UpdateServer(data.id, data));
+        }
+    }
+
+    dispatch_events(&lock.webhooks, server);
+}
+
+async fn handle_hung(state: GlobalState, id: usize, pid: u32) {
+    let (dir, cfg) = {
+        let lock = state.read().unwrap();
+        let config = &lock.servers[id].data.config;
+        (config.dir.clone(), config.watchdog.clone())
+    };
+
+    let cfg = match cfg {
+        Some(cfg) => cfg,
+        None => return,
+    };
+
+    if cfg.thread_dump {
+        watchdog::thread_dump(pid, Path::new(&dir)).await;
+    }
+
+    if cfg.restart {
+        restart_hung(&state, id, pid);
+    }
+}
+
 async fn update_servers(state: GlobalState) {
     use std::time::Duration;
     use tokio::time::delay_for;
 
     let duration = Duration::from_secs(5);
+    let watchdog = systemd::watchdog_enabled();
 
     loop {
         delay_for(duration).await;
-        let mut lock = state.write().unwrap();
-        let tx = lock.tx.clone();
 
-        for server in lock.servers.iter_mut() {
-            if server.update_status() {
-                let data = server.data.clone();
-                let cmd = CommandResponse::UpdateServer(data.id, data);
-                let msg = match serialize_ws(&cmd) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        error!("failed to serialize ws message: {}", e);
-                        break;
-                    }
-                };
-                if let Err(e) = tx.send(msg) {
-                    error!("failed to update server: {}", e);
-                }
-            }
+        if watchdog {
+            systemd::notify("WATCHDOG=1");
+        }
+
+        let (probes, restarts) = update_status(&state);
+        for id in restarts {
+            tokio::spawn(start::restart(state.clone(), id));
+        }
+
+        let probes = probes
+            .into_iter()
+            .map(|(id, (host, port))| async move { (id, slp::ping(&host, port).await) });
+        let results = future::join_all(probes).await;
+
+        for (id, pid) in update_health(&state, results) {
+            tokio::spawn(handle_hung(state.clone(), id, pid));
         }
     }
 }
@@ -227,10 +555,11 @@ async fn update_servers(state: GlobalState) {
 async fn update_clients(mut rx: UnboundedReceiver<Message>, state: GlobalState) {
     loop {
         if let Some(msg) = rx.recv().await {
-            let lock = state.read().unwrap();
-            for client in lock.clients.iter() {
-                client.send(Ok(msg.clone())).unwrap();
-            }
+            let mut lock = state.write().unwrap();
+            lock.clients
+                .retain(|client| client.send(Ok(msg.clone())).is_ok());
+            lock.socket_clients
+                .retain(|client| client.send(Ok(msg.clone())).is_ok());
         }
     }
 }
@@ -255,6 +584,7 @@ async fn server_init(matches: &clap::ArgMatches<'static>) -> Result<(), ServerEr
         _ => {}
     }
 
+    let router = config.router.clone();
     let (tx, rx) = unbounded_channel();
     let state = Arc::new(RwLock::new(State::new(config, tx)));
 
@@ -268,6 +598,39 @@ async fn server_init(matches: &clap::ArgMatches<'static>) -> Result<(), ServerEr
         update_servers(s).await;
     });
 
+    if !state.read().unwrap().sync_groups.is_empty() {
+        tokio::task::spawn(sync::run(state.clone()));
+    }
+
+    tokio::task::spawn(schedule::run(state.clone()));
+
+    let sleepers: Vec<_> = state
+        .read()
+        .unwrap()
+        .servers
+        .iter()
+        .filter(|s| s.data.config.wake_on_connect.is_some())
+        .map(|s| s.data.id)
+        .collect();
+    for id in sleepers {
+        tokio::task::spawn(wake::run(state.clone(), id));
+    }
+
+    if let Some(router) = router {
+        tokio::task::spawn(router::run(state.clone(), router));
+    }
+
+    #[cfg(unix)]
+    {
+        let path = Path::new(matches.value_of("socket").unwrap_or(SOCKET_PATH)).to_path_buf();
+        let s = state.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) = socket::listen(&path, s).await {
+                error!("control socket failed: {}", e);
+            }
+        });
+    }
+
     let state = warp::any().map(move || state.clone());
 
     let path = env::current_dir()
@@ -276,22 +639,54 @@ async fn server_init(matches: &clap::ArgMatches<'static>) -> Result<(), ServerEr
 
     let dirs = warp::get().and(fs::dir(path.clone()));
     let idx = warp::get().and(fs::file(path.join("index.html")));
-    let ws = warp::path("cmd").and(warp::ws()).and(state).map(handle_ws);
+    let ws = warp::path("cmd")
+        .and(warp::ws())
+        .and(state.clone())
+        .map(handle_ws);
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(state)
+        .map(serve_metrics);
 
-    let routes = dirs.or(ws).or(idx);
+    let routes = metrics.or(dirs).or(ws).or(idx);
 
     let addr = ([0, 0, 0, 0], 3000);
     info!("starting server");
-    let server = warp::serve(routes).run(addr);
+    let (addr, server) = warp::serve(routes).try_bind_ephemeral(addr)?;
+    info!("listening on {}", addr);
+    systemd::notify("READY=1");
 
     let _ = tokio::join!(server_task, client_task, server);
 
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
+fn init_logger(journald: bool) {
+    use log::LevelFilter;
+
+    if journald {
+        let level = env::var("MINED_LOG")
+            .ok()
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(LevelFilter::Info);
+
+        match systemd::JournalLogger::init(level) {
+            Ok(_) => return,
+            Err(e) => eprintln!("failed to connect to journald: {}", e),
+        }
+    }
+
+    pretty_env_logger::init_custom_env("MINED_LOG");
+}
+
+#[cfg(not(target_os = "linux"))]
+fn init_logger(_journald: bool) {
+    pretty_env_logger::init_custom_env("MINED_LOG");
+}
+
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init_custom_env("MINED_LOG");
     let matches = App::new("barista")
         .arg(
             Arg::with_name("config")
@@ -301,6 +696,19 @@ async fn main() {
                 .help("sets a custom config")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("socket")
+                .long("socket")
+                .short("s")
+                .value_name("FILE")
+                .help("sets the path of the control socket")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("journald")
+                .long("journald")
+                .help("logs to journald instead of stderr"),
+        )
         .arg(
             Arg::with_name("website-path")
                 .value_name("DIR")
@@ -308,6 +716,8 @@ async fn main() {
                 .takes_value(true),
         )
         .get_matches();
+
+    init_logger(matches.is_present("journald"));
     server_init(&matches)
         .await
         .map_err(|e| error!("{}", e))
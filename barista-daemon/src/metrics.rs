@@ -0,0 +1,254 @@
+use super::State;
+use barista::server::Status;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+static STATUSES: [Status; 6] = [
+    Status::Open,
+    Status::Starting,
+    Status::Stopping,
+    Status::Stopped,
+    Status::Crashed,
+    Status::Unresponsive,
+];
+
+#[derive(Debug, Default)]
+struct CommandStats {
+    count: u64,
+    errors: u64,
+    duration: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    commands: HashMap<&'static str, CommandStats>,
+}
+
+impl Metrics {
+    pub fn record(&mut self, cmd: &'static str, elapsed: Duration, failed: bool) {
+        let stats = self.commands.entry(cmd).or_default();
+        stats.count += 1;
+        stats.duration += elapsed;
+        if failed {
+            stats.errors += 1;
+        }
+    }
+}
+
+// rss in bytes and cpu time in seconds
+#[cfg(target_os = "linux")]
+pub fn process_stats(pid: u32) -> Option<(u64, f64)> {
+    use nix::unistd::{sysconf, SysconfVar};
+
+    let page_size = sysconf(SysconfVar::PAGE_SIZE).ok()??;
+    let ticks = sysconf(SysconfVar::CLK_TCK).ok()??;
+
+    let statm = std::fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    let rss = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+
+    // the command name can contain spaces, so start after the closing paren
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    // utime and stime are the 14th and 15th fields, the first two are before the paren
+    let utime = fields.nth(11)?.parse::<u64>().ok()?;
+    let stime = fields.next()?.parse::<u64>().ok()?;
+
+    Some((
+        rss * page_size as u64,
+        (utime + stime) as f64 / ticks as f64,
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_stats(_pid: u32) -> Option<(u64, f64)> {
+    None
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+pub fn render(state: &State) -> String {
+    let mut out = String::new();
+    let labels: Vec<String> = state
+        .servers
+        .iter()
+        .map(|s| format!("id=\"{}\",name=\"{}\"", s.data.id, escape(&s.data.name)))
+        .collect();
+
+    header(
+        &mut out,
+        "mined_server_status",
+        "gauge",
+        "current status of the server",
+    );
+    for (server, labels) in state.servers.iter().zip(&labels) {
+        for status in STATUSES.iter() {
+            let value = (server.data.status == *status) as u8;
+            writeln!(
+                out,
+                "mined_server_status{{{},status=\"{}\"}} {}",
+                labels, status, value
+            )
+            .unwrap();
+        }
+    }
+
+    header(&mut out, "mined_server_players", "gauge", "players online");
+    for (server, labels) in state.servers.iter().zip(&labels) {
+        writeln!(
+            out,
+            "mined_server_players{{{}}} {}",
+            labels, server.data.player_count
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "mined_server_uptime_seconds",
+        "gauge",
+        "seconds since the server was started",
+    );
+    for (server, labels) in state.servers.iter().zip(&labels) {
+        let uptime = server.uptime().map(|u| u.as_secs_f64()).unwrap_or(0.0);
+        writeln!(out, "mined_server_uptime_seconds{{{}}} {}", labels, uptime).unwrap();
+    }
+
+    header(
+        &mut out,
+        "mined_server_starts_total",
+        "counter",
+        "times the server has been started",
+    );
+    for (server, labels) in state.servers.iter().zip(&labels) {
+        writeln!(
+            out,
+            "mined_server_starts_total{{{}}} {}",
+            labels, server.starts
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "mined_server_crashes_total",
+        "counter",
+        "times the server has crashed",
+    );
+    for (server, labels) in state.servers.iter().zip(&labels) {
+        writeln!(
+            out,
+            "mined_server_crashes_total{{{}}} {}",
+            labels, server.crashes
+        )
+        .unwrap();
+    }
+
+    let stats: Vec<_> = state
+        .servers
+        .iter()
+        .zip(&labels)
+        .filter_map(|(s, l)| s.pid().and_then(process_stats).map(|stats| (l, stats)))
+        .collect();
+
+    header(
+        &mut out,
+        "mined_server_memory_rss_bytes",
+        "gauge",
+        "resident memory of the server process",
+    );
+    for (labels, (rss, _)) in stats.iter() {
+        writeln!(out, "mined_server_memory_rss_bytes{{{}}} {}", labels, rss).unwrap();
+    }
+
+    header(
+        &mut out,
+        "mined_server_cpu_seconds_total",
+        "counter",
+        "cpu time used by the server process",
+    );
+    for (labels, (_, cpu)) in stats.iter() {
+        writeln!(out, "mined_server_cpu_seconds_total{{{}}} {}", labels, cpu).unwrap();
+    }
+
+    header(
+        &mut out,
+        "mined_clients_connected",
+        "gauge",
+        "connected clients, by how they connected",
+    );
+    writeln!(
+        out,
+        "mined_clients_connected{{transport=\"websocket\"}} {}",
+        state.clients.len()
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "mined_clients_connected{{transport=\"socket\"}} {}",
+        state.socket_clients.len()
+    )
+    .unwrap();
+
+    let mut commands: Vec<_> = state.metrics.commands.iter().collect();
+    commands.sort_by_key(|(name, _)| *name);
+
+    header(&mut out, "mined_commands_total", "counter", "commands run");
+    for (name, stats) in commands.iter() {
+        writeln!(
+            out,
+            "mined_commands_total{{command=\"{}\"}} {}",
+            name, stats.count
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "mined_command_errors_total",
+        "counter",
+        "commands that returned an error",
+    );
+    for (name, stats) in commands.iter() {
+        writeln!(
+            out,
+            "mined_command_errors_total{{command=\"{}\"}} {}",
+            name, stats.errors
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "mined_command_duration_seconds",
+        "summary",
+        "time spent running commands",
+    );
+    for (name, stats) in commands.iter() {
+        writeln!(
+            out,
+            "mined_command_duration_seconds_sum{{command=\"{}\"}} {}",
+            name,
+            stats.duration.as_secs_f64()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "mined_command_duration_seconds_count{{command=\"{}\"}} {}",
+            name, stats.count
+        )
+        .unwrap();
+    }
+
+    out
+}
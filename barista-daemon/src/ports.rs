@@ -0,0 +1,141 @@
+use super::properties::Properties;
+use super::Server;
+use barista::command::*;
+use barista::config::{PortRange, RouterConfig};
+use log::{info, warn};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+
+// an empty address listens on every interface as well
+fn is_wildcard(ip: &str) -> bool {
+    ip.is_empty() || ip == "0.0.0.0" || ip == "::"
+}
+
+fn overlaps(a: &(String, u16), b: &(String, u16)) -> bool {
+    a.1 == b.1 && (a.0 == b.0 || is_wildcard(&a.0) || is_wildcard(&b.0))
+}
+
+// only a port that's taken counts, an address that isn't on this machine is the server's problem
+pub fn is_free(addr: &(String, u16)) -> bool {
+    match TcpListener::bind((addr.0.as_str(), addr.1)) {
+        Ok(_) => true,
+        Err(e) => e.kind() != io::ErrorKind::AddrInUse,
+    }
+}
+
+fn bind_address(server: &Server) -> Option<(String, u16)> {
+    match Properties::load(Path::new(&server.data.config.dir)) {
+        Ok(props) => Some(props.bind_address()),
+        Err(e) => {
+            warn!(
+                "failed to read server.properties of {}: {}",
+                server.data.name, e
+            );
+            None
+        }
+    }
+}
+
+fn router_address(router: Option<&RouterConfig>) -> Option<(String, u16)> {
+    let addr: SocketAddr = router?.listen.parse().ok()?;
+    Some((addr.ip().to_string(), addr.port()))
+}
+
+// gives servers without a server-port one from the range that nothing else is using
+pub fn assign(servers: &[Server], range: &PortRange, router: Option<&RouterConfig>) {
+    let mut props = vec![];
+    let mut taken: Vec<_> = router_address(router).into_iter().collect();
+
+    for server in servers.iter() {
+        match Properties::load(Path::new(&server.data.config.dir)) {
+            Ok(p) if p.get("server-port").is_some() => taken.push(p.bind_address()),
+            Ok(p) => props.push((server, p)),
+            Err(e) => warn!(
+                "failed to read server.properties of {}: {}",
+                server.data.name, e
+            ),
+        }
+    }
+
+    for (server, mut props) in props {
+        let ip = props.bind_address().0;
+        let port = (range.start..=range.end).find(|port| {
+            let addr = (ip.clone(), *port);
+            !taken.iter().any(|t| overlaps(t, &addr)) && is_free(&addr)
+        });
+
+        let port = match port {
+            Some(port) => port,
+            None => {
+                warn!(
+                    "no free ports left between {} and {} for {}",
+                    range.start, range.end, server.data.name
+                );
+                continue;
+            }
+        };
+
+        props.set("server-port", &port.to_string());
+        match props.save() {
+            Ok(_) => {
+                info!("gave {} port {}", server.data.name, port);
+                taken.push((ip, port));
+            }
+            Err(e) => warn!(
+                "failed to save server.properties of {}: {}",
+                server.data.name, e
+            ),
+        }
+    }
+}
+
+// servers sharing a port can still take turns, so this only warns
+pub fn check_config(servers: &[Server], router: Option<&RouterConfig>) {
+    let router = router_address(router);
+    let addrs: Vec<_> = servers
+        .iter()
+        .filter_map(|s| bind_address(s).map(|addr| (&s.data.name, addr)))
+        .collect();
+
+    for (i, (name, addr)) in addrs.iter().enumerate() {
+        for (other, other_addr) in addrs[i + 1..].iter() {
+            if overlaps(addr, other_addr) {
+                warn!(
+                    "{} and {} both use port {}, only one of them can run at a time",
+                    name, other, addr.1
+                );
+            }
+        }
+
+        if matches!(&router, Some(r) if overlaps(addr, r)) {
+            warn!("{} uses port {}, which the router listens on", name, addr.1);
+        }
+    }
+}
+
+// another server might not have opened its port yet, so this looks at the running ones instead of
+// the port itself
+pub fn conflict(servers: &[Server], id: usize) -> Option<(u16, String)> {
+    let server = servers.get(id)?;
+    if server.is_running() {
+        return None;
+    }
+
+    let addr = bind_address(server)?;
+    servers
+        .iter()
+        .find(|s| {
+            s.data.id != id
+                && s.is_running()
+                && matches!(s.bind_address(), Some(a) if overlaps(a, &addr))
+        })
+        .map(|other| (addr.1, other.data.name.clone()))
+}
+
+pub fn check(servers: &[Server], id: usize) -> Result<(), CommandError> {
+    match conflict(servers, id) {
+        Some((port, name)) => Err(CommandError::PortInUse(port, Some(name))),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,160 @@
+use super::jar::{self, JarInfo};
+use super::java;
+use super::ports;
+use super::properties::Properties;
+use super::Server;
+use barista::command::*;
+use barista::java::JavaRuntime;
+use barista::preflight::PreflightError;
+use log::warn;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+static MIN_JAVA_VERSION: u32 = 8;
+// enough for the world to keep saving for a while
+static MIN_FREE_SPACE: u64 = 512 * 1024 * 1024;
+static PROBE_FILE: &str = ".barista-preflight";
+
+// what the checks need from the server, so they can run without holding the lock
+pub struct Target {
+    dir: PathBuf,
+    dir_name: String,
+    jar: PathBuf,
+    java: Option<String>,
+    runtimes: Vec<JavaRuntime>,
+    // a running server has its own port open, and a sleeping one gives it up when it's started
+    check_port: bool,
+}
+
+pub struct Report {
+    pub jar: JarInfo,
+    // the java binary to start the server with
+    pub java: String,
+    pub errors: Vec<PreflightError>,
+}
+
+impl Report {
+    // a port that's taken keeps its own error, everything else is listed together
+    pub fn result(&self, id: usize) -> Result<(), CommandError> {
+        if let Some(PreflightError::PortInUse(port, name)) = self
+            .errors
+            .iter()
+            .find(|e| matches!(e, PreflightError::PortInUse(_, _)))
+        {
+            return Err(CommandError::PortInUse(*port, name.clone()));
+        }
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CommandError::PreflightFailed(id, self.errors.clone()))
+        }
+    }
+}
+
+// the only reliable way to tell is to try
+fn is_writable(dir: &Path) -> bool {
+    let path = dir.join(PROBE_FILE);
+    let writable = fs::write(&path, b"").is_ok();
+    let _ = fs::remove_file(&path);
+    writable
+}
+
+fn check_dir(dir: &Path, name: &str, errors: &mut Vec<PreflightError>) {
+    if !dir.is_dir() {
+        return errors.push(PreflightError::MissingDirectory(name.to_string()));
+    }
+
+    if !is_writable(dir) {
+        errors.push(PreflightError::DirectoryNotWritable(name.to_string()));
+    }
+
+    match fs2::available_space(dir) {
+        Ok(available) if available < MIN_FREE_SPACE => {
+            errors.push(PreflightError::LowDiskSpace(available, MIN_FREE_SPACE))
+        }
+        Ok(_) => {}
+        Err(e) => warn!("failed to check the free space in {}: {}", name, e),
+    }
+}
+
+fn check_jar(jar: &Path) -> Option<PreflightError> {
+    let name = jar.display().to_string();
+    if !jar.is_file() {
+        return Some(PreflightError::MissingJar(name));
+    }
+
+    let file = match File::open(jar) {
+        Ok(file) => file,
+        Err(e) => return Some(PreflightError::InvalidJar(name, e.to_string())),
+    };
+    zip::ZipArchive::new(file)
+        .err()
+        .map(|e| PreflightError::InvalidJar(name, e.to_string()))
+}
+
+fn check_java(java: &str, required: Option<u32>) -> Option<PreflightError> {
+    let required = required.unwrap_or(MIN_JAVA_VERSION);
+
+    match java::version(java) {
+        Ok(Some(version)) if version < required => {
+            Some(PreflightError::IncompatibleJava(version, required))
+        }
+        Ok(Some(_)) => None,
+        Ok(None) => Some(PreflightError::UnknownJavaVersion(java.to_string())),
+        Err(_) => Some(PreflightError::JavaNotFound(java.to_string())),
+    }
+}
+
+impl Target {
+    pub fn new(server: &Server) -> Self {
+        let cfg = &server.data.config;
+        let dir = PathBuf::from(&cfg.dir);
+
+        Self {
+            jar: dir.join(&cfg.jar),
+            dir,
+            dir_name: cfg.dir.clone(),
+            java: cfg.java.clone(),
+            runtimes: server.runtimes.clone(),
+            check_port: !server.is_running() && !server.sleeping,
+        }
+    }
+
+    // everything the server needs that doesn't depend on the other servers, this blocks on the
+    // disk and on running java
+    pub fn check(self) -> Report {
+        let mut errors = vec![];
+
+        check_dir(&self.dir, &self.dir_name, &mut errors);
+        errors.extend(check_jar(&self.jar));
+
+        // the jar might have been replaced since it was last read
+        let info = jar::read(&self.jar);
+        let java = match java::resolve(self.java.as_deref(), info.required_java, &self.runtimes) {
+            Ok(java) => {
+                errors.extend(check_java(&java, info.required_java));
+                java
+            }
+            Err(e) => {
+                errors.push(e);
+                java::DEFAULT_JAVA.to_string()
+            }
+        };
+
+        if self.check_port {
+            if let Ok(props) = Properties::load(&self.dir) {
+                let bind = props.bind_address();
+                if !ports::is_free(&bind) {
+                    errors.push(PreflightError::PortInUse(bind.1, None));
+                }
+            }
+        }
+
+        Report {
+            jar: info,
+            java,
+            errors,
+        }
+    }
+}
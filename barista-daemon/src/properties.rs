@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+static DEFAULT_PORT: u16 = 25565;
+static DEFAULT_RCON_PORT: u16 = 25575;
+
+#[derive(Debug, Clone)]
+enum Line {
+    // the original line is kept so untouched entries are written back as they were
+    Entry {
+        key: String,
+        value: String,
+        raw: String,
+    },
+    Other(String),
+}
+
+// server.properties, keeping comments, blank lines and the order of keys
+#[derive(Debug, Clone)]
+pub struct Properties {
+    path: PathBuf,
+    lines: Vec<Line>,
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    // `\u` escapes are utf-16, so characters outside the bmp take two of them
+    let mut units = vec![];
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.clone().next() == Some('u') {
+            chars.next();
+            let code: String = chars.by_ref().take(4).collect();
+            if let Ok(unit) = u16::from_str_radix(&code, 16) {
+                units.push(unit);
+            }
+            continue;
+        }
+
+        out.extend(std::char::decode_utf16(units.drain(..)).filter_map(Result::ok));
+
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+
+    out.extend(std::char::decode_utf16(units.drain(..)).filter_map(Result::ok));
+    out
+}
+
+fn escape(value: &str, key: bool) -> String {
+    let mut out = String::with_capacity(value.len());
+
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '=' | ':' | '#' | '!' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' ' if key || i == 0 => out.push_str("\\ "),
+            c if (c as u32) < 0x20 || (c as u32) > 0x7e => {
+                let mut buf = [0; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    out.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+fn parse_line(line: &str) -> Option<(String, String)> {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+        return None;
+    }
+
+    // the key ends at the first unescaped `=`, `:` or whitespace
+    let mut escaped = false;
+    let split = trimmed.char_indices().find(|(_, c)| {
+        let found = !escaped && (*c == '=' || *c == ':' || c.is_whitespace());
+        escaped = !escaped && *c == '\\';
+        found
+    });
+
+    let (key, value) = match split {
+        Some((i, _)) => {
+            let value = trimmed[i..].trim_start();
+            let value = value
+                .strip_prefix('=')
+                .or_else(|| value.strip_prefix(':'))
+                .unwrap_or(value);
+            (&trimmed[..i], value.trim_start())
+        }
+        None => (trimmed, ""),
+    };
+
+    Some((unescape(key), unescape(value)))
+}
+
+impl Properties {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join("server.properties")
+    }
+
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        Self::open(Self::path(dir))
+    }
+
+    // any file in the same format, like eula.txt
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let lines = contents
+            .lines()
+            .map(|line| match parse_line(line) {
+                Some((key, value)) => Line::Entry {
+                    key,
+                    value,
+                    raw: line.to_string(),
+                },
+                None => Line::Other(line.to_string()),
+            })
+            .collect();
+
+        Ok(Self { path, lines })
+    }
+
+    // writes to a temporary file first, so a failed write can't leave a truncated file behind
+    pub fn save(&self) -> io::Result<()> {
+        let mut contents = String::new();
+        for line in self.lines.iter() {
+            match line {
+                Line::Entry { raw, .. } | Line::Other(raw) => contents.push_str(raw),
+            }
+            contents.push('\n');
+        }
+
+        let tmp = self.path.with_extension("properties.tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry { key, value, .. } => Some((key, value)),
+            Line::Other(_) => None,
+        })
+    }
+
+    // the last entry for a key, matching `to_map`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries()
+            .filter(|(k, _)| *k == key)
+            .last()
+            .map(|(_, v)| v.as_str())
+    }
+
+    // later entries win if a key is repeated, the same as minecraft
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.entries()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    // returns whether the value changed
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        if self.get(key) == Some(value) {
+            return false;
+        }
+
+        let raw = format!("{}={}", escape(key, true), escape(value, false));
+        let existing = self.lines.iter_mut().rev().find_map(|line| match line {
+            Line::Entry { key: k, value, raw } if k == key => Some((value, raw)),
+            _ => None,
+        });
+
+        match existing {
+            Some((v, r)) => {
+                *v = value.to_string();
+                *r = raw;
+            }
+            None => self.lines.push(Line::Entry {
+                key: key.to_string(),
+                value: value.to_string(),
+                raw,
+            }),
+        }
+
+        true
+    }
+
+    pub fn port(&self) -> u16 {
+        self.get("server-port")
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(DEFAULT_PORT)
+    }
+
+    // the address players connect to, falling back to localhost if the server binds every
+    // interface
+    pub fn address(&self) -> (String, u16) {
+        let ip = match self.get("server-ip") {
+            Some(ip) if !ip.is_empty() => ip.to_string(),
+            _ => "127.0.0.1".to_string(),
+        };
+
+        (ip, self.port())
+    }
+
+    // the address the server listens on
+    pub fn bind_address(&self) -> (String, u16) {
+        let ip = match self.get("server-ip") {
+            Some(ip) if !ip.is_empty() => ip.to_string(),
+            _ => "0.0.0.0".to_string(),
+        };
+
+        (ip, self.port())
+    }
+
+    // the rcon port and password, if rcon is enabled
+    pub fn rcon(&self) -> Option<(u16, String)> {
+        if self.get("enable-rcon") != Some("true") {
+            return None;
+        }
+
+        let password = self.get("rcon.password").filter(|p| !p.is_empty())?;
+        let port = self
+            .get("rcon.port")
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(DEFAULT_RCON_PORT);
+
+        Some((port, password.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescapes_values() {
+        assert_eq!(unescape(r"a\=b\:c\\d"), r"a=b:c\d");
+        assert_eq!(unescape(r"line\none\ttab"), "line\none\ttab");
+        assert_eq!(unescape(r"\u00a7cred"), "§cred");
+        // a surrogate pair for a character outside the bmp
+        assert_eq!(unescape(r"\ud83d\ude00!"), "😀!");
+        assert_eq!(unescape("trailing\\"), "trailing");
+    }
+
+    #[test]
+    fn escapes_values() {
+        assert_eq!(escape("a=b:c", false), r"a\=b\:c");
+        assert_eq!(escape(" leading space", false), r"\ leading space");
+        assert_eq!(escape("key with space", true), r"key\ with\ space");
+        assert_eq!(escape("§c😀", false), r"\u00a7c\ud83d\ude00");
+        assert_eq!(escape("tab\there", false), r"tab\there");
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        for value in &[
+            " motd=§aHello: world!",
+            r"C:\server\world",
+            "😀\n# not a comment",
+        ] {
+            assert_eq!(unescape(&escape(value, false)), *value);
+            assert_eq!(unescape(&escape(value, true)), *value);
+        }
+    }
+
+    #[test]
+    fn duplicated_keys_use_the_last_entry() {
+        let path = std::env::temp_dir().join(format!(
+            "barista-properties-{}.properties",
+            std::process::id()
+        ));
+        fs::write(&path, "server-port=25565\nmotd=first\nserver-port=25566\n").unwrap();
+
+        let mut props = Properties::open(path.clone()).unwrap();
+        assert_eq!(props.port(), 25566);
+        assert_eq!(props.to_map()["server-port"], "25566");
+
+        assert!(!props.set("server-port", "25566"));
+        assert!(props.set("server-port", "25567"));
+        props.save().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "server-port=25565\nmotd=first\nserver-port=25567\n"
+        );
+        assert_eq!(props.get("server-port"), Some("25567"));
+        assert_eq!(props.to_map()["server-port"], "25567");
+    }
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(
+            parse_line("server-port=25565"),
+            Some(("server-port".to_string(), "25565".to_string()))
+        );
+        assert_eq!(
+            parse_line("  motd : hello world"),
+            Some(("motd".to_string(), "hello world".to_string()))
+        );
+        assert_eq!(
+            parse_line(r"level\ name=world"),
+            Some(("level name".to_string(), "world".to_string()))
+        );
+        assert_eq!(parse_line("pvp"), Some(("pvp".to_string(), String::new())));
+        assert_eq!(parse_line("# a comment"), None);
+        assert_eq!(parse_line("! also a comment"), None);
+        assert_eq!(parse_line("   "), None);
+    }
+}
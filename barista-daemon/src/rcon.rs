@@ -0,0 +1,216 @@
+use std::io;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::prelude::*;
+use tokio::time::timeout;
+
+static RCON_TIMEOUT: Duration = Duration::from_secs(10);
+static MAX_PACKET_LEN: i32 = 4096 + 10;
+
+static TYPE_RESPONSE: i32 = 0;
+static TYPE_COMMAND: i32 = 2;
+static TYPE_LOGIN: i32 = 3;
+
+#[derive(Debug)]
+pub enum RconError {
+    IoError(io::Error),
+    AuthFailed,
+    InvalidResponse(String),
+    Timeout,
+}
+
+impl From<io::Error> for RconError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl std::fmt::Display for RconError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Self::IoError(e) => format!("io error: {}", e),
+            Self::AuthFailed => "wrong rcon password".to_string(),
+            Self::InvalidResponse(e) => format!("invalid rcon response: {}", e),
+            Self::Timeout => "timed out".to_string(),
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+struct Packet {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+pub struct Rcon {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl Rcon {
+    async fn write_packet(&mut self, kind: i32, body: &str) -> Result<i32, RconError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        // the length doesn't include itself, but does include the id, type and two nul bytes
+        let mut buf = Vec::with_capacity(body.len() + 14);
+        buf.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&kind.to_le_bytes());
+        buf.extend_from_slice(body.as_bytes());
+        buf.extend_from_slice(&[0, 0]);
+
+        self.stream.write_all(&buf).await?;
+        Ok(id)
+    }
+
+    async fn read_packet(&mut self) -> Result<Packet, RconError> {
+        let len = self.stream.read_i32_le().await?;
+        if !(10..=MAX_PACKET_LEN).contains(&len) {
+            return Err(RconError::InvalidResponse(format!(
+                "invalid packet length {}",
+                len
+            )));
+        }
+
+        let mut buf = vec![0; len as usize];
+        self.stream.read_exact(&mut buf).await?;
+
+        let id = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let kind = i32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let body = String::from_utf8_lossy(&buf[8..buf.len() - 2]).to_string();
+
+        Ok(Packet { id, kind, body })
+    }
+
+    pub async fn connect(host: &str, port: u16, password: &str) -> Result<Self, RconError> {
+        let stream = TcpStream::connect((host, port)).await?;
+        let mut rcon = Self { stream, next_id: 1 };
+
+        let id = rcon.write_packet(TYPE_LOGIN, password).await?;
+        let res = rcon.read_packet().await?;
+
+        // a failed login is answered with an id of -1
+        if res.id == -1 {
+            return Err(RconError::AuthFailed);
+        }
+        if res.id != id || res.kind != TYPE_COMMAND {
+            return Err(RconError::InvalidResponse(
+                "unexpected login response".to_string(),
+            ));
+        }
+
+        Ok(rcon)
+    }
+
+    pub async fn command(&mut self, cmd: &str) -> Result<String, RconError> {
+        let id = self.write_packet(TYPE_COMMAND, cmd).await?;
+        // long responses are split over several packets, the server answers packets in order so
+        // the reply to this one marks the end of the response
+        let end = self.write_packet(TYPE_RESPONSE, "").await?;
+
+        let mut body = String::new();
+        loop {
+            let res = self.read_packet().await?;
+            if res.id == end {
+                return Ok(body);
+            }
+            if res.id != id {
+                return Err(RconError::InvalidResponse(format!(
+                    "unexpected packet id {}",
+                    res.id
+                )));
+            }
+            body.push_str(&res.body);
+        }
+    }
+}
+
+// runs a single command over a new connection
+pub async fn run(host: &str, port: u16, password: &str, cmd: &str) -> Result<String, RconError> {
+    let res = timeout(RCON_TIMEOUT, async {
+        let mut rcon = Rcon::connect(host, port, password).await?;
+        rcon.command(cmd).await
+    })
+    .await;
+
+    res.unwrap_or(Err(RconError::Timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    static PASSWORD: &str = "hunter2";
+
+    async fn read(stream: &mut TcpStream) -> Option<(i32, i32, String)> {
+        let len = stream.read_i32_le().await.ok()?;
+        let mut buf = vec![0; len as usize];
+        stream.read_exact(&mut buf).await.ok()?;
+
+        let id = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let kind = i32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let body = String::from_utf8(buf[8..buf.len() - 2].to_vec()).unwrap();
+        Some((id, kind, body))
+    }
+
+    async fn write(stream: &mut TcpStream, id: i32, kind: i32, body: &str) {
+        let mut buf = vec![];
+        buf.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&kind.to_le_bytes());
+        buf.extend_from_slice(body.as_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        stream.write_all(&buf).await.unwrap();
+    }
+
+    // behaves like a vanilla server, splitting the response to each command in two
+    async fn serve() -> u16 {
+        let mut listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let (id, kind, password) = read(&mut stream).await.unwrap();
+            assert_eq!(kind, TYPE_LOGIN);
+            if password != PASSWORD {
+                return write(&mut stream, -1, TYPE_COMMAND, "").await;
+            }
+            write(&mut stream, id, TYPE_COMMAND, "").await;
+
+            while let Some((id, kind, body)) = read(&mut stream).await {
+                if kind == TYPE_COMMAND {
+                    write(&mut stream, id, TYPE_RESPONSE, "ran ").await;
+                    write(&mut stream, id, TYPE_RESPONSE, &body).await;
+                } else {
+                    write(&mut stream, id, TYPE_RESPONSE, "Unknown request 0").await;
+                }
+            }
+        });
+
+        port
+    }
+
+    #[tokio::test]
+    async fn runs_commands() {
+        let port = serve().await;
+        let mut rcon = Rcon::connect("127.0.0.1", port, PASSWORD).await.unwrap();
+
+        assert_eq!(rcon.command("list").await.unwrap(), "ran list");
+        assert_eq!(rcon.command("say hi").await.unwrap(), "ran say hi");
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_password() {
+        let port = serve().await;
+
+        match run("127.0.0.1", port, "wrong", "list").await {
+            Err(RconError::AuthFailed) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use super::backup;
+use super::dedup::{self, Repo};
+use super::{GlobalState, ServerContext};
+use barista::command::*;
+use chrono::Utc;
+use log::{error, info, warn};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn begin(state: &GlobalState, id: usize) -> Result<(), CommandError> {
+    let mut lock = state.write()?;
+    let server = lock
+        .servers
+        .get_mut(id)
+        .ok_or(CommandError::NonExistentServer(id))?;
+
+    if server.pid().is_some() {
+        return Err(CommandError::ServerRunning(id));
+    }
+    if server.backing_up {
+        return Err(CommandError::BackupInProgress(id));
+    }
+    if server.restoring {
+        return Err(CommandError::RestoreInProgress(id));
+    }
+
+    server.restoring = true;
+    Ok(())
+}
+
+fn finish(state: &GlobalState, id: usize, name: &str, res: &Result<(), CommandError>) {
+    if let Ok(mut lock) = state.write() {
+        let server = &mut lock.servers[id];
+        let _ctx = ServerContext::enter(&server.data);
+        server.restoring = false;
+
+        match res {
+            Ok(_) => info!("restored {} from {}", server.data.name, name),
+            Err(e) => error!(
+                "failed to restore {} from {}: {}",
+                server.data.name, name, e
+            ),
+        }
+    }
+}
+
+fn verify(archive: &Path) -> Result<(), CommandError> {
+    let expected = match backup::expected_checksum(archive)? {
+        Some(sum) => sum,
+        None => {
+            warn!(
+                "{} has no checksum, it can't be verified",
+                archive.display()
+            );
+            return Ok(());
+        }
+    };
+
+    let actual = backup::checksum(archive)?;
+    if !actual.eq_ignore_ascii_case(&expected) {
+        return Err(CommandError::RestoreFailed(format!(
+            "checksum mismatch, expected {} but got {}",
+            expected, actual
+        )));
+    }
+
+    Ok(())
+}
+
+fn extract(archive: &Path, staging: &Path) -> io::Result<()> {
+    fs::create_dir_all(staging)?;
+    let decoder = zstd::Decoder::new(File::open(archive)?)?;
+    tar::Archive::new(decoder).unpack(staging)
+}
+
+// moves every top level entry of the staging directory into place, moving whatever was there
+// into the safety copy first. returns the names that were moved so they can be rolled back
+fn swap(dir: &Path, staging: &Path, safety: &Path, moved: &mut Vec<PathBuf>) -> io::Result<()> {
+    fs::create_dir_all(safety)?;
+
+    for entry in fs::read_dir(staging)? {
+        let name = PathBuf::from(entry?.file_name());
+        let target = dir.join(&name);
+
+        if target.exists() {
+            fs::rename(&target, safety.join(&name))?;
+        }
+        moved.push(name.clone());
+        fs::rename(staging.join(&name), &target)?;
+    }
+
+    Ok(())
+}
+
+fn rollback(dir: &Path, safety: &Path, moved: &[PathBuf]) -> io::Result<()> {
+    for name in moved.iter() {
+        let target = dir.join(name);
+        let saved = safety.join(name);
+
+        if target.exists() {
+            if target.is_dir() {
+                fs::remove_dir_all(&target)?;
+            } else {
+                fs::remove_file(&target)?;
+            }
+        }
+        if saved.exists() {
+            fs::rename(&saved, &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+// snapshots don't need verifying first, every chunk is checked as it's read
+fn unpack(backup_dir: &Path, name: &str, staging: &Path) -> io::Result<()> {
+    if dedup::is_snapshot(name) {
+        Repo::new(backup_dir).restore(name, staging)
+    } else {
+        extract(&backup_dir.join(name), staging)
+    }
+}
+
+fn restore(dir: &Path, backup_dir: &Path, name: &str) -> Result<(), CommandError> {
+    let failed = |e: io::Error| CommandError::RestoreFailed(e.to_string());
+
+    if !dedup::is_snapshot(name) {
+        verify(&backup_dir.join(name))?;
+    }
+
+    // both are inside the server's directory so everything can be renamed into place
+    let stamp = Utc::now().format("%Y-%m-%d_%H-%M-%S");
+    let staging = dir.join(format!(".restore-{}", stamp));
+    let safety = dir.join(format!(".restore-safety-{}", stamp));
+
+    if let Err(e) = unpack(backup_dir, name, &staging) {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(failed(e));
+    }
+
+    let mut moved = vec![];
+    if let Err(e) = swap(dir, &staging, &safety, &mut moved) {
+        error!(
+            "failed to move the restored files into place, rolling back: {}",
+            e
+        );
+        if let Err(e) = rollback(dir, &safety, &moved) {
+            error!(
+                "rollback failed, the old files are still in {}: {}",
+                safety.display(),
+                e
+            );
+            return Err(failed(e));
+        }
+        let _ = fs::remove_dir_all(&staging);
+        let _ = fs::remove_dir_all(&safety);
+        return Err(failed(e));
+    }
+
+    let _ = fs::remove_dir_all(&staging);
+    if let Err(e) = fs::remove_dir_all(&safety) {
+        warn!("failed to remove {}: {}", safety.display(), e);
+    }
+
+    Ok(())
+}
+
+pub async fn restore_backup(state: GlobalState, id: usize, name: String) -> CommandResult {
+    let (backup_dir, _) = backup::server_backups(&state, id)?;
+    backup::path(&backup_dir, &name)?;
+    let dir = PathBuf::from(&state.read()?.servers[id].data.config.dir);
+
+    begin(&state, id)?;
+
+    let backup = name.clone();
+    let res = tokio::task::spawn_blocking(move || restore(&dir, &backup_dir, &backup))
+        .await
+        .map_err(|e| CommandError::RestoreFailed(e.to_string()))
+        .and_then(|res| res);
+    finish(&state, id, &name, &res);
+    res?;
+
+    let lock = state.read()?;
+    let data = lock.servers[id].data.clone();
+    Ok(CommandResponse::UpdateServer(id, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a server directory that's removed once the test is done
+    struct Server {
+        root: PathBuf,
+        dir: PathBuf,
+    }
+
+    impl Server {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "barista-restore-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&root);
+            let dir = root.join("server");
+
+            fs::create_dir_all(dir.join("world")).unwrap();
+            fs::write(dir.join("world/level.dat"), b"old level").unwrap();
+            fs::write(dir.join("server.properties"), b"motd=old").unwrap();
+            fs::write(dir.join("ops.json"), b"[]").unwrap();
+
+            Self { root, dir }
+        }
+
+        fn read(&self, path: &str) -> String {
+            fs::read_to_string(self.dir.join(path)).unwrap()
+        }
+
+        fn names(&self) -> Vec<String> {
+            let mut names: Vec<_> = fs::read_dir(&self.dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn write_archive(path: &Path, files: &[(&str, &[u8])]) {
+        let encoder = zstd::Encoder::new(File::create(path).unwrap(), 3).unwrap();
+        let mut tar = tar::Builder::new(encoder);
+        for (name, data) in files.iter() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, *data).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn rolls_back_a_failed_swap() {
+        let server = Server::new("rollback");
+        let staging = server.root.join("staging");
+        let safety = server.root.join("safety");
+
+        fs::create_dir_all(staging.join("world")).unwrap();
+        fs::write(staging.join("world/level.dat"), b"new level").unwrap();
+        fs::write(staging.join("server.properties"), b"motd=new").unwrap();
+        fs::write(staging.join("ops.json"), b"[{}]").unwrap();
+        fs::write(staging.join("whitelist.json"), b"[]").unwrap();
+
+        // the old world can't be moved aside since something's already in its place
+        fs::create_dir_all(safety.join("world")).unwrap();
+        fs::write(safety.join("world/in-the-way"), b"").unwrap();
+
+        let mut moved = vec![];
+        assert!(swap(&server.dir, &staging, &safety, &mut moved).is_err());
+        rollback(&server.dir, &safety, &moved).unwrap();
+
+        assert_eq!(
+            server.names(),
+            vec!["ops.json", "server.properties", "world"]
+        );
+        assert_eq!(server.read("world/level.dat"), "old level");
+        assert_eq!(server.read("server.properties"), "motd=old");
+        assert_eq!(server.read("ops.json"), "[]");
+    }
+
+    #[test]
+    fn refuses_a_bad_checksum() {
+        let server = Server::new("checksum");
+        let backup_dir = server.root.join("backups");
+        fs::create_dir_all(&backup_dir).unwrap();
+
+        let name = "2021-01-04_12-00-00.tar.zst";
+        let archive = backup_dir.join(name);
+        write_archive(
+            &archive,
+            &[
+                ("world/level.dat", b"new level"),
+                ("server.properties", b"motd=new"),
+            ],
+        );
+        fs::write(
+            backup::checksum_path(&archive),
+            format!("{}  {}\n", "0".repeat(64), name),
+        )
+        .unwrap();
+
+        match restore(&server.dir, &backup_dir, name) {
+            Err(CommandError::RestoreFailed(e)) => assert!(e.starts_with("checksum mismatch")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            server.names(),
+            vec!["ops.json", "server.properties", "world"]
+        );
+        assert_eq!(server.read("world/level.dat"), "old level");
+        assert_eq!(server.read("server.properties"), "motd=old");
+
+        // the same archive restores once the checksum matches
+        let sum = backup::checksum(&archive).unwrap();
+        fs::write(
+            backup::checksum_path(&archive),
+            format!("{}  {}\n", sum, name),
+        )
+        .unwrap();
+        restore(&server.dir, &backup_dir, name).unwrap();
+        assert_eq!(
+            server.names(),
+            vec!["ops.json", "server.properties", "world"]
+        );
+        assert_eq!(server.read("world/level.dat"), "new level");
+        assert_eq!(server.read("server.properties"), "motd=new");
+    }
+}
@@ -0,0 +1,150 @@
+use barista::backup::BackupInfo;
+use barista::config::RetentionConfig;
+use chrono::{Datelike, Local, TimeZone};
+use std::collections::HashSet;
+
+// keeps the newest backup of each period, for the last `count` periods that have a backup
+fn keep_periods<K, F>(backups: &[BackupInfo], keep: &mut [bool], count: usize, period: F)
+where
+    K: Eq + std::hash::Hash,
+    F: Fn(&BackupInfo) -> Option<K>,
+{
+    let mut seen = HashSet::new();
+    for (i, backup) in backups.iter().enumerate() {
+        if seen.len() >= count {
+            break;
+        }
+        if let Some(period) = period(backup) {
+            if seen.insert(period) {
+                keep[i] = true;
+            }
+        }
+    }
+}
+
+// takes backups sorted newest first and returns the ones to delete
+pub fn prune<'a>(backups: &'a [BackupInfo], cfg: &RetentionConfig) -> Vec<&'a BackupInfo> {
+    let local = |b: &BackupInfo| Local.timestamp_opt(b.created, 0).single();
+    // a backup with a time that can't be placed in a period is never pruned
+    let mut keep: Vec<bool> = backups.iter().map(|b| local(b).is_none()).collect();
+
+    // never prune the backup that was just made
+    if let Some(newest) = keep.first_mut() {
+        *newest = true;
+    }
+
+    keep.iter_mut().take(cfg.keep_last).for_each(|k| *k = true);
+    keep_periods(backups, &mut keep, cfg.daily, |b| {
+        local(b).map(|d| d.date_naive())
+    });
+    keep_periods(backups, &mut keep, cfg.weekly, |b| {
+        let week = local(b)?.iso_week();
+        Some((week.year(), week.week()))
+    });
+    keep_periods(backups, &mut keep, cfg.monthly, |b| {
+        let date = local(b)?;
+        Some((date.year(), date.month()))
+    });
+
+    if let Some(max_size) = cfg.max_size {
+        let mut size = 0;
+        for (i, backup) in backups.iter().enumerate() {
+            if !keep[i] {
+                continue;
+            }
+            size += backup.size;
+            if size > max_size && i != 0 {
+                keep[i] = false;
+            }
+        }
+    }
+
+    backups
+        .iter()
+        .zip(keep.iter())
+        .filter(|(_, keep)| !**keep)
+        .map(|(backup, _)| backup)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static DAY: i64 = 24 * 60 * 60;
+    // noon on a monday, so a few hours either way stays on the same day
+    static START: i64 = 1_609_761_600;
+
+    // `hours` after the start, oldest first, returned newest first like the backups are listed
+    fn backups(hours: &[i64]) -> Vec<BackupInfo> {
+        hours
+            .iter()
+            .rev()
+            .map(|h| BackupInfo {
+                name: format!("{}.zip", h),
+                size: 10,
+                created: START + h * 60 * 60,
+            })
+            .collect()
+    }
+
+    fn config(yaml: &str) -> RetentionConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn pruned(backups: &[BackupInfo], cfg: &RetentionConfig) -> Vec<String> {
+        prune(backups, cfg).iter().map(|b| b.name.clone()).collect()
+    }
+
+    #[test]
+    fn keeps_the_newest() {
+        let backups = backups(&[0, 1, 2]);
+        assert_eq!(pruned(&backups, &config("{}")), vec!["1.zip", "0.zip"]);
+    }
+
+    #[test]
+    fn keeps_the_last_few() {
+        let backups = backups(&[0, 1, 2, 3, 4]);
+        let cfg = config("keep_last: 2");
+        assert_eq!(pruned(&backups, &cfg), vec!["2.zip", "1.zip", "0.zip"]);
+    }
+
+    #[test]
+    fn keeps_one_a_day() {
+        let backups = backups(&[0, 2, 24, 26, 48, 50]);
+        let cfg = config("daily: 2");
+        assert_eq!(
+            pruned(&backups, &cfg),
+            vec!["48.zip", "24.zip", "2.zip", "0.zip"]
+        );
+    }
+
+    #[test]
+    fn keeps_one_a_week() {
+        let hours: Vec<i64> = (0..21).map(|d| d * DAY / 3600).collect();
+        let backups = backups(&hours);
+        let cfg = config("weekly: 3");
+
+        let pruned = pruned(&backups, &cfg);
+        let kept: Vec<_> = backups
+            .iter()
+            .filter(|b| !pruned.contains(&b.name))
+            .map(|b| b.name.clone())
+            .collect();
+        assert_eq!(kept, vec!["480.zip", "312.zip", "144.zip"]);
+    }
+
+    #[test]
+    fn limits_the_total_size() {
+        let backups = backups(&[0, 1, 2, 3]);
+        let cfg = config("{keep_last: 4, max_size: 25}");
+        assert_eq!(pruned(&backups, &cfg), vec!["1.zip", "0.zip"]);
+    }
+
+    #[test]
+    fn keeps_backups_with_invalid_times() {
+        let mut backups = backups(&[0, 1, 2]);
+        backups[2].created = i64::MAX;
+        assert_eq!(pruned(&backups, &config("{}")), vec!["1.zip"]);
+    }
+}
@@ -0,0 +1,237 @@
+use super::properties::Properties;
+use super::slp::{self, Reader};
+use super::GlobalState;
+use barista::config::RouterConfig;
+use log::{debug, error, info, warn};
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::*;
+use tokio::time::timeout;
+
+static HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// a handshake is a few numbers and a hostname of at most 255 characters
+static MAX_HANDSHAKE_LEN: i32 = 1024;
+static PROXY_SIGNATURE: [u8; 12] = [
+    0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a,
+];
+
+struct Handshake {
+    // the packet as it was sent, so it can be passed on
+    raw: Vec<u8>,
+    host: String,
+    login: bool,
+}
+
+// forge adds markers after a nul byte, and some clients keep the trailing dot
+fn hostname(address: &str) -> String {
+    address
+        .split('\0')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+fn matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => pattern == host,
+    }
+}
+
+async fn read_handshake(stream: &mut TcpStream) -> io::Result<Handshake> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let len = slp::read_varint(stream).await?;
+    if len <= 0 || len > MAX_HANDSHAKE_LEN {
+        return Err(invalid("invalid handshake length"));
+    }
+    let mut body = vec![0; len as usize];
+    stream.read_exact(&mut body).await?;
+
+    let mut reader = Reader::new(&body);
+    if reader.varint()? != 0x00 {
+        return Err(invalid("not a handshake"));
+    }
+    reader.varint()?;
+    let host = hostname(&reader.string()?);
+    reader.u16()?;
+    let login = reader.varint()? == 2;
+
+    let mut raw = vec![];
+    slp::write_varint(&mut raw, len);
+    raw.extend(body);
+    Ok(Handshake { raw, host, login })
+}
+
+// the address of the server a hostname belongs to
+fn route(state: &GlobalState, cfg: &RouterConfig, host: &str) -> Option<(String, u16)> {
+    let dir = {
+        let lock = state.read().ok()?;
+        let server = lock
+            .servers
+            .iter()
+            .find(|s| s.data.config.hostnames.iter().any(|h| matches(h, host)))
+            .or_else(|| {
+                let default = cfg.default.as_ref()?;
+                lock.servers.iter().find(|s| &s.data.config.name == default)
+            })?;
+        PathBuf::from(&server.data.config.dir)
+    };
+
+    match Properties::load(&dir) {
+        Ok(props) => Some(props.address()),
+        Err(_) => Some(("127.0.0.1".to_string(), 25565)),
+    }
+}
+
+fn ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+// tells the server where the player really connected from, and to
+fn proxy_header(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let mut buf = PROXY_SIGNATURE.to_vec();
+    // version 2, proxied connection
+    buf.push(0x21);
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            buf.push(0x11);
+            buf.extend_from_slice(&12u16.to_be_bytes());
+            buf.extend_from_slice(&src.octets());
+            buf.extend_from_slice(&dst.octets());
+        }
+        (src, dst) => {
+            buf.push(0x21);
+            buf.extend_from_slice(&36u16.to_be_bytes());
+            buf.extend_from_slice(&ipv6(src).octets());
+            buf.extend_from_slice(&ipv6(dst).octets());
+        }
+    }
+
+    buf.extend_from_slice(&src.port().to_be_bytes());
+    buf.extend_from_slice(&dst.port().to_be_bytes());
+    buf
+}
+
+async fn disconnect(stream: &mut TcpStream, msg: &str) -> io::Result<()> {
+    let reason = serde_json::json!({ "text": msg }).to_string();
+    let mut data = vec![];
+    slp::write_string(&mut data, &reason);
+    stream.write_all(&slp::packet(0x00, &data)).await
+}
+
+async fn handle(state: GlobalState, cfg: &RouterConfig, mut client: TcpStream) -> io::Result<()> {
+    let peer = client.peer_addr()?;
+    let local = client.local_addr()?;
+
+    let handshake = timeout(HANDSHAKE_TIMEOUT, read_handshake(&mut client))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out reading handshake"))??;
+
+    let (host, port) = match route(&state, cfg, &handshake.host) {
+        Some(backend) => backend,
+        None => {
+            debug!("no server for {}", handshake.host);
+            if handshake.login {
+                disconnect(
+                    &mut client,
+                    &format!("There's no server at {}", handshake.host),
+                )
+                .await?;
+            }
+            return Ok(());
+        }
+    };
+
+    let mut server = TcpStream::connect((host.as_str(), port)).await?;
+    if cfg.proxy_protocol {
+        server.write_all(&proxy_header(peer, local)).await?;
+    }
+    server.write_all(&handshake.raw).await?;
+
+    let (mut client_rx, mut client_tx) = tokio::io::split(client);
+    let (mut server_rx, mut server_tx) = tokio::io::split(server);
+
+    let upstream = async {
+        let res = tokio::io::copy(&mut client_rx, &mut server_tx).await;
+        let _ = server_tx.shutdown().await;
+        res
+    };
+    let downstream = async {
+        let res = tokio::io::copy(&mut server_rx, &mut client_tx).await;
+        let _ = client_tx.shutdown().await;
+        res
+    };
+
+    tokio::try_join!(upstream, downstream)?;
+    Ok(())
+}
+
+pub async fn run(state: GlobalState, cfg: RouterConfig) {
+    if let Some(default) = &cfg.default {
+        let lock = state.read().unwrap();
+        if !lock.servers.iter().any(|s| &s.data.config.name == default) {
+            warn!("the router's default route is unknown server {}", default);
+        }
+    }
+
+    let mut listener = match TcpListener::bind(cfg.listen.as_str()).await {
+        Ok(listener) => listener,
+        Err(e) => return error!("failed to start the router on {}: {}", cfg.listen, e),
+    };
+    info!("routing players from {}", cfg.listen);
+
+    let cfg = Arc::new(cfg);
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let (state, cfg) = (state.clone(), cfg.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = handle(state, &cfg, stream).await {
+                        debug!("connection from {} failed: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => warn!("failed to accept a connection: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_hostnames() {
+        assert_eq!(hostname("Play.Example.com"), "play.example.com");
+        assert_eq!(hostname("play.example.com."), "play.example.com");
+        assert_eq!(hostname("play.example.com\0FML2\0"), "play.example.com");
+        assert_eq!(hostname(""), "");
+    }
+
+    #[test]
+    fn matches_exact_hostnames() {
+        assert!(matches("play.example.com", "play.example.com"));
+        assert!(matches("Play.Example.com", "play.example.com"));
+        assert!(!matches("play.example.com", "creative.example.com"));
+        assert!(!matches("example.com", "play.example.com"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("*.example.com", "play.example.com"));
+        assert!(matches("*.example.com", "a.b.example.com"));
+        assert!(!matches("*.example.com", "example.com"));
+        assert!(!matches("*.example.com", "badexample.com"));
+    }
+}
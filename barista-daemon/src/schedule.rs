@@ -0,0 +1,255 @@
+use super::backup;
+use super::shutdown;
+use super::start;
+use super::{broadcast, GlobalState};
+use barista::command::*;
+use barista::config::{ScheduleAction, ScheduleConfig, ServerConfig};
+use barista::schedule::{ScheduleInfo, ScheduleRun};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use log::{error, info, warn};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::delay_for;
+
+static SCHEDULE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    config: ScheduleConfig,
+    cron: cron::Schedule,
+    tz: Tz,
+    enabled: bool,
+    next: Option<DateTime<Utc>>,
+    last_run: Option<ScheduleRun>,
+}
+
+impl Schedule {
+    // schedules with an invalid expression or timezone are skipped
+    fn resolve(server: &str, config: &ScheduleConfig) -> Option<Self> {
+        // the cron crate wants seconds, crontab doesn't have them
+        let expr = if config.cron.split_whitespace().count() == 5 {
+            format!("0 {}", config.cron)
+        } else {
+            config.cron.clone()
+        };
+
+        let cron = match cron::Schedule::from_str(&expr) {
+            Ok(cron) => cron,
+            Err(e) => {
+                warn!(
+                    "schedule {} of server {} has an invalid cron expression: {}",
+                    config.name, server, e
+                );
+                return None;
+            }
+        };
+
+        let tz = match &config.timezone {
+            Some(tz) => match tz.parse() {
+                Ok(tz) => tz,
+                Err(e) => {
+                    warn!(
+                        "schedule {} of server {} has an invalid timezone: {}",
+                        config.name, server, e
+                    );
+                    return None;
+                }
+            },
+            None => Tz::UTC,
+        };
+
+        let mut schedule = Self {
+            config: config.clone(),
+            cron,
+            tz,
+            enabled: config.enabled,
+            next: None,
+            last_run: None,
+        };
+        schedule.plan(Utc::now());
+        Some(schedule)
+    }
+
+    fn plan(&mut self, now: DateTime<Utc>) {
+        self.next = if self.enabled {
+            self.cron
+                .after(&now.with_timezone(&self.tz))
+                .next()
+                .map(|t| t.with_timezone(&Utc))
+        } else {
+            None
+        };
+    }
+
+    fn info(&self) -> ScheduleInfo {
+        ScheduleInfo {
+            name: self.config.name.clone(),
+            cron: self.config.cron.clone(),
+            timezone: self.tz.name().to_string(),
+            action: self.config.action.clone(),
+            enabled: self.enabled,
+            next_run: self.next.map(|t| t.timestamp()),
+            last_run: self.last_run.clone(),
+        }
+    }
+}
+
+pub fn resolve(config: &ServerConfig) -> Vec<Schedule> {
+    config
+        .schedules
+        .iter()
+        .filter_map(|s| Schedule::resolve(&config.name, s))
+        .collect()
+}
+
+fn infos(state: &GlobalState, id: usize) -> Result<Vec<ScheduleInfo>, CommandError> {
+    let lock = state.read()?;
+    let schedules = lock
+        .schedules
+        .get(id)
+        .ok_or(CommandError::NonExistentServer(id))?;
+    Ok(schedules.iter().map(|s| s.info()).collect())
+}
+
+fn find(
+    state: &GlobalState,
+    id: usize,
+    name: &str,
+) -> Result<(usize, ScheduleConfig), CommandError> {
+    let lock = state.read()?;
+    let schedules = lock
+        .schedules
+        .get(id)
+        .ok_or(CommandError::NonExistentServer(id))?;
+
+    schedules
+        .iter()
+        .position(|s| s.config.name == name)
+        .map(|i| (i, schedules[i].config.clone()))
+        .ok_or_else(|| CommandError::NonExistentSchedule(name.to_string()))
+}
+
+// counts down first if the schedule has a delay
+async fn shut_down(
+    state: &GlobalState,
+    id: usize,
+    delay: Option<u64>,
+    restart: bool,
+) -> Result<(), CommandError> {
+    let res = shutdown::request(state.clone(), id, delay, restart).await?;
+    broadcast(&state.read()?.tx, &res);
+    Ok(())
+}
+
+async fn execute(
+    state: &GlobalState,
+    id: usize,
+    config: &ScheduleConfig,
+) -> Result<(), CommandError> {
+    match &config.action {
+        ScheduleAction::Start => {
+            let res = start::start(state, id).await?;
+            broadcast(&state.read()?.tx, &res);
+            Ok(())
+        }
+        ScheduleAction::Stop => shut_down(state, id, config.delay, false).await,
+        ScheduleAction::Restart => shut_down(state, id, config.delay, true).await,
+        ScheduleAction::Backup => backup::backup(state.clone(), id).await.map(|_| ()),
+        ScheduleAction::Say(msg) => {
+            state.write()?.servers[id].send_console(&format!("say {}", msg))
+        }
+        ScheduleAction::Command(cmd) => state.write()?.servers[id].send_console(cmd),
+    }
+}
+
+// records how it went and lets every client know
+async fn run_schedule(
+    state: &GlobalState,
+    id: usize,
+    index: usize,
+    config: &ScheduleConfig,
+) -> Result<(), CommandError> {
+    let time = Utc::now().timestamp();
+    let res = execute(state, id, config).await;
+
+    if let Ok(mut guard) = state.write() {
+        let lock = &mut *guard;
+        let name = &lock.servers[id].data.name;
+        let schedule = &mut lock.schedules[id][index];
+        match &res {
+            Ok(_) => info!("ran schedule {} of {}", schedule.config.name, name),
+            Err(e) => error!(
+                "schedule {} of {} failed: {}",
+                schedule.config.name, name, e
+            ),
+        }
+
+        schedule.last_run = Some(ScheduleRun {
+            time,
+            error: res.as_ref().err().map(|e| e.to_string()),
+        });
+        let infos = lock.schedules[id].iter().map(|s| s.info()).collect();
+        broadcast(&lock.tx, &CommandResponse::Schedules(id, infos));
+    }
+
+    res
+}
+
+pub fn list_schedules(state: &GlobalState, id: usize) -> CommandResult {
+    Ok(CommandResponse::Schedules(id, infos(state, id)?))
+}
+
+pub async fn run_now(state: GlobalState, id: usize, name: String) -> CommandResult {
+    let (index, config) = find(&state, id, &name)?;
+    run_schedule(&state, id, index, &config)
+        .await
+        .map_err(|e| CommandError::ScheduleFailed(e.to_string()))?;
+
+    Ok(CommandResponse::Schedules(id, infos(&state, id)?))
+}
+
+// only lasts until the daemon restarts, the config decides after that
+pub fn set_enabled(state: &GlobalState, id: usize, name: &str, enabled: bool) -> CommandResult {
+    let (index, _) = find(state, id, name)?;
+    {
+        let mut lock = state.write()?;
+        let schedule = &mut lock.schedules[id][index];
+        schedule.enabled = enabled;
+        schedule.plan(Utc::now());
+    }
+
+    Ok(CommandResponse::Schedules(id, infos(state, id)?))
+}
+
+// returns every schedule that's due, and plans its next run
+fn due(state: &GlobalState) -> Vec<(usize, usize, ScheduleConfig)> {
+    let mut lock = state.write().unwrap();
+    let now = Utc::now();
+    let mut due = vec![];
+
+    for (id, schedules) in lock.schedules.iter_mut().enumerate() {
+        for (index, schedule) in schedules.iter_mut().enumerate() {
+            if matches!(schedule.next, Some(next) if next <= now) {
+                due.push((id, index, schedule.config.clone()));
+                schedule.plan(now);
+            }
+        }
+    }
+
+    due
+}
+
+pub async fn run(state: GlobalState) {
+    loop {
+        delay_for(SCHEDULE_INTERVAL).await;
+
+        // backups can take a while, so each one runs by itself
+        for (id, index, config) in due(&state) {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let _ = run_schedule(&state, id, index, &config).await;
+            });
+        }
+    }
+}
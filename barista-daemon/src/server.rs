@@ -1,8 +1,25 @@
 use barista::command::*;
+use barista::config::Event;
+use barista::java::JavaRuntime;
 use barista::server::ServerData;
-use barista::server::Status;
+use barista::server::{ServerInfo, Status, StopReason};
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
 use std::path::Path;
-use std::process::{self, Child};
+use std::process::{self, Child, ChildStdin, ChildStdout, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, TryRecvError};
+
+use super::eula;
+use super::jar::{self, JarInfo};
+use super::preflight::Report;
+use super::properties::Properties;
+use super::slp::SlpError;
+use super::ServerContext;
+
+static CONSOLE_BUFFER: usize = 1024;
 
 #[cfg(windows)]
 use winapi::shared::{minwindef::BOOL, windef::HWND};
@@ -10,53 +27,451 @@ use winapi::shared::{minwindef::BOOL, windef::HWND};
 #[derive(Debug)]
 pub struct Server {
     pub data: ServerData,
+    pub starts: u64,
+    pub crashes: u64,
+    pub backing_up: bool,
+    pub restoring: bool,
+    // bumped whenever a countdown to stopping is started or cancelled, so old ones give up
+    pub shutdown_token: u64,
+    // set while wake_on_connect is listening on the server's port
+    pub sleeping: bool,
+    // set while the checks before a start are running
+    pub starting: bool,
+    // the java installations it can choose from
+    pub runtimes: Vec<JavaRuntime>,
     process: Option<Child>,
+    // a process that was told to stop but hasn't exited yet
+    exiting: Option<Child>,
+    restart_pending: bool,
+    stdin: Option<ChildStdin>,
+    started: Option<Instant>,
+    console: Option<broadcast::Sender<String>>,
+    console_rx: Option<broadcast::Receiver<String>>,
+    players: Vec<String>,
+    events: Vec<(Event, Option<String>)>,
+    address: Option<(String, u16)>,
+    // where the running server listens
+    bind: Option<(String, u16)>,
+    failures: u32,
+    // when the last player left, or the server started if nobody has joined
+    idle_since: Option<Instant>,
+    rcon: Option<(u16, String)>,
+    eula_failed: bool,
+}
+
+fn is_player_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
 impl Server {
     pub fn new(data: ServerData) -> Self {
         Self {
             data,
+            starts: 0,
+            crashes: 0,
+            backing_up: false,
+            restoring: false,
+            shutdown_token: 0,
+            sleeping: false,
+            starting: false,
+            runtimes: vec![],
             process: None,
+            exiting: None,
+            restart_pending: false,
+            stdin: None,
+            started: None,
+            console: None,
+            console_rx: None,
+            players: vec![],
+            events: vec![],
+            address: None,
+            bind: None,
+            failures: 0,
+            idle_since: None,
+            rcon: None,
+            eula_failed: false,
         }
     }
 
-    pub fn start(&mut self) -> CommandResult {
-        let mut cfg = self.data.config.clone();
-        let dir = Path::new(&cfg.dir);
-        let jar = dir.join(cfg.jar);
+    // creates the server's directory if it's missing, so a new server can be set up from scratch
+    pub fn bootstrap(&mut self) {
+        let dir = Path::new(&self.data.config.dir);
+        if !dir.exists() {
+            match std::fs::create_dir_all(dir) {
+                Ok(_) => info!("created {} for {}", dir.display(), self.data.name),
+                Err(e) => warn!("failed to create {}: {}", dir.display(), e),
+            }
+        }
+
+        self.check_eula();
+        self.inspect_jar();
+    }
+
+    fn inspect_jar(&mut self) {
+        let jar = Path::new(&self.data.config.dir).join(&self.data.config.jar);
+        self.set_jar_info(jar::read(&jar));
+    }
+
+    fn set_jar_info(&mut self, info: JarInfo) {
+        self.data.minecraft_version = info.minecraft_version;
+        self.data.required_java = info.required_java;
+    }
+
+    fn check_eula(&mut self) -> bool {
+        self.data.eula_accepted = match eula::accepted(Path::new(&self.data.config.dir)) {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("failed to read eula.txt: {}", e);
+                false
+            }
+        };
+        self.data.eula_accepted
+    }
+
+    pub fn accept_eula(&mut self) -> CommandResult {
+        eula::accept(Path::new(&self.data.config.dir))?;
+        info!("accepted the eula for {}", self.data.name);
+        self.data.eula_accepted = true;
+
+        Ok(CommandResponse::UpdateServer(
+            self.data.id,
+            self.data.clone(),
+        ))
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.process.as_ref().map(|c| c.id())
+    }
+
+    pub fn uptime(&self) -> Option<Duration> {
+        self.started.map(|s| s.elapsed())
+    }
+
+    // where to ping the running server
+    pub fn address(&self) -> Option<(String, u16)> {
+        self.address.clone()
+    }
+
+    pub fn bind_address(&self) -> Option<&(String, u16)> {
+        self.bind.as_ref()
+    }
+
+    // runs a command on the server's console, there's no way to get its output
+    pub fn send_console(&mut self, cmd: &str) -> Result<(), CommandError> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or(CommandError::ServerNotRunning(self.data.id))?;
+
+        writeln!(stdin, "{}", cmd)?;
+        stdin.flush()?;
+        Ok(())
+    }
+
+    pub fn get_properties(&self) -> CommandResult {
+        let props = Properties::load(Path::new(&self.data.config.dir))?;
+        Ok(CommandResponse::Properties(self.data.id, props.to_map()))
+    }
+
+    // returns the new properties, and whether the server needs a restart for them to apply
+    pub fn set_properties(
+        &mut self,
+        changes: BTreeMap<String, String>,
+    ) -> Result<(CommandResponse, bool), CommandError> {
+        if let Some(key) = changes.keys().find(|k| k.trim().is_empty()) {
+            return Err(CommandError::InvalidProperty(key.clone()));
+        }
+
+        let mut props = Properties::load(Path::new(&self.data.config.dir))?;
+        let mut changed = false;
+        for (key, value) in changes.iter() {
+            changed |= props.set(key, value);
+        }
 
-        let mut args = vec!["-jar".to_string(), jar.to_str().unwrap().to_string()];
+        if !changed {
+            return Ok((
+                CommandResponse::Properties(self.data.id, props.to_map()),
+                false,
+            ));
+        }
+
+        props.save()?;
+        info!("updated server.properties of {}", self.data.name);
+
+        let restart = self.is_running() && !self.data.restart_required;
+        if restart {
+            self.data.restart_required = true;
+        }
+
+        Ok((
+            CommandResponse::Properties(self.data.id, props.to_map()),
+            restart,
+        ))
+    }
+
+    // where to connect to rcon and the password to use
+    pub fn rcon(&self) -> Result<(String, u16, String), CommandError> {
+        let (host, _) = self
+            .address
+            .clone()
+            .ok_or(CommandError::ServerNotRunning(self.data.id))?;
+        let (port, password) = self
+            .rcon
+            .clone()
+            .ok_or(CommandError::RconNotEnabled(self.data.id))?;
+
+        Ok((host, port, password))
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.data.status == Status::Open || self.data.status == Status::Unresponsive
+    }
+
+    // stopped with no process left that could still be using the port, and not about to start
+    pub fn is_stopped(&self) -> bool {
+        self.data.status == Status::Stopped
+            && self.process.is_none()
+            && self.exiting.is_none()
+            && !self.restart_pending
+            && !self.starting
+            && !self.restoring
+    }
+
+    // counts a failed ping towards the watchdog, marking the server as hung once it runs out
+    fn record_failure(&mut self) {
+        let cfg = match self.data.config.watchdog.as_ref() {
+            Some(cfg) => cfg,
+            None => return,
+        };
+
+        let grace = Duration::from_secs(cfg.startup_grace);
+        match self.uptime() {
+            Some(uptime) if uptime >= grace => {}
+            _ => return,
+        }
+
+        self.failures += 1;
+        if self.data.status == Status::Open && self.failures >= cfg.max_failures {
+            warn!(
+                "{} failed {} pings in a row, marking it as unresponsive",
+                self.data.name, self.failures
+            );
+            self.data.status = Status::Unresponsive;
+            self.events.push((Event::Unresponsive, None));
+        }
+    }
+
+    pub fn update_health(&mut self, res: Result<ServerInfo, SlpError>) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+
+        let prev = self.data.clone();
+        match res {
+            Ok(info) => {
+                if !self.data.responding {
+                    info!("{} is responding", self.data.name);
+                }
+                if self.data.status == Status::Unresponsive {
+                    info!("{} recovered", self.data.name);
+                    self.data.status = Status::Open;
+                }
+                self.failures = 0;
+                self.data.player_count = info.online_players;
+                self.data.info = Some(info);
+                self.data.responding = true;
+            }
+            Err(e) => {
+                if self.data.responding {
+                    warn!("{} is running but not responding: {}", self.data.name, e);
+                }
+                self.data.responding = false;
+                self.record_failure();
+            }
+        }
+
+        self.data != prev
+    }
+
+    pub fn push_event(&mut self, event: Event) {
+        self.events.push((event, None));
+    }
+
+    // every line the server prints from now on
+    pub fn console(&self) -> Option<broadcast::Receiver<String>> {
+        self.console.as_ref().map(|tx| tx.subscribe())
+    }
+
+    pub fn take_events(&mut self) -> Vec<(Event, Option<String>)> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn watch_console(&mut self, stdout: ChildStdout) {
+        let (tx, rx) = broadcast::channel(CONSOLE_BUFFER);
+        let console = tx.clone();
+        let data = self.data.clone();
+
+        thread::spawn(move || {
+            let _ctx = ServerContext::enter(&data);
+            let mut stdout = BufReader::new(stdout);
+            let mut buf = vec![];
+            while let Ok(n) = stdout.read_until(b'\n', &mut buf) {
+                if n == 0 {
+                    break;
+                }
+
+                let line = String::from_utf8_lossy(&buf).trim_end().to_string();
+                debug!("{}: {}", data.name, line);
+                let _ = console.send(line);
+                buf.clear();
+            }
+        });
+
+        self.console = Some(tx);
+        self.console_rx = Some(rx);
+    }
+
+    // vanilla logs look like `[12:00:00] [Server thread/INFO]: Steve joined the game`
+    fn handle_line(&mut self, line: &str) -> bool {
+        let msg = match line.find("]: ") {
+            Some(i) => &line[i + 3..],
+            None => return false,
+        };
+
+        if msg.starts_with("You need to agree to the EULA") {
+            self.eula_failed = true;
+            return false;
+        }
+
+        if let Some(name) = msg.strip_suffix(" joined the game") {
+            if is_player_name(name) {
+                self.players.push(name.to_string());
+                self.events
+                    .push((Event::PlayerJoin, Some(name.to_string())));
+            }
+        } else if let Some(name) = msg.strip_suffix(" left the game") {
+            if is_player_name(name) {
+                self.players.retain(|p| p != name);
+                self.events
+                    .push((Event::PlayerLeave, Some(name.to_string())));
+            }
+        } else {
+            return false;
+        }
+
+        self.data.player_count = self.players.len() as u64;
+        true
+    }
+
+    fn read_console(&mut self) -> bool {
+        let mut lines = vec![];
+        if let Some(rx) = self.console_rx.as_mut() {
+            loop {
+                match rx.try_recv() {
+                    Ok(line) => lines.push(line),
+                    Err(TryRecvError::Lagged(n)) => {
+                        warn!("{} skipped {} console lines", self.data.name, n)
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+
+        let mut changed = false;
+        for line in lines.iter() {
+            changed |= self.handle_line(line);
+        }
+        changed
+    }
+
+    fn clear_process(&mut self) {
+        if let Some(c) = self.process.take() {
+            self.exiting = Some(c);
+        }
+        self.stdin = None;
+        self.started = None;
+        self.console = None;
+        self.console_rx = None;
+        self.players.clear();
+        self.address = None;
+        self.bind = None;
+        self.rcon = None;
+        self.eula_failed = false;
+        self.failures = 0;
+        self.data.player_count = 0;
+        self.data.info = None;
+        self.data.responding = false;
+        self.data.pending_shutdown = None;
+    }
+
+    // only `start::start` calls this, after running the preflight checks without the lock
+    pub fn start(&mut self, report: Report) -> CommandResult {
+        // it might have been started while the checks ran
+        if self.is_running() {
+            return Err(CommandError::ServerRunning(self.data.id));
+        }
+        self.restart_pending = false;
+
+        if self.restoring {
+            return Err(CommandError::RestoreInProgress(self.data.id));
+        }
+
+        if !self.check_eula() {
+            return Err(CommandError::EulaNotAccepted(self.data.id));
+        }
+
+        self.set_jar_info(report.jar.clone());
+        report.result(self.data.id)?;
+
+        let cfg = &self.data.config;
+        let dir = Path::new(&cfg.dir);
+        let mut cmd = process::Command::new(&report.java);
+        cmd.args(&cfg.args).arg("-jar").arg(dir.join(&cfg.jar));
 
         #[cfg(not(windows))]
-        args.append(&mut vec!["nogui".to_string()]);
+        cmd.arg("nogui");
 
-        cfg.args.append(&mut args);
+        let (address, bind, rcon) = match Properties::load(dir) {
+            Ok(props) => (props.address(), props.bind_address(), props.rcon()),
+            Err(e) => {
+                warn!("failed to read server.properties: {}", e);
+                let address = ("127.0.0.1".to_string(), 25565);
+                (address, ("0.0.0.0".to_string(), 25565), None)
+            }
+        };
 
-        process::Command::new("java")
-            .args(cfg.args)
-            .current_dir(dir)
+        cmd.current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
             .spawn()
-            .map(|c| {
+            .map(|mut c| {
+                self.stdin = c.stdin.take();
+                if let Some(stdout) = c.stdout.take() {
+                    self.watch_console(stdout);
+                }
                 self.process = Some(c);
+                self.address = Some(address);
+                self.bind = Some(bind);
+                self.rcon = rcon;
+                self.data.restart_required = false;
+                self.data.stop_reason = None;
+                self.started = Some(Instant::now());
+                self.starts += 1;
                 self.data.status = Status::Open;
+                self.events.push((Event::Started, None));
                 CommandResponse::UpdateServer(self.data.id, self.data.clone())
             })
-            .map_err(|e| match e.raw_os_error() {
-                Some(c) => CommandError::SystemError(c),
-                None => CommandError::UnknownSystemError,
-            })
+            .map_err(CommandError::from)
     }
 
     #[cfg(unix)]
     pub fn stop(&mut self) -> CommandResult {
         use nix::sys::signal::{self, Signal};
         use nix::unistd::Pid;
-        signal::kill(
-            Pid::from_raw(self.process.take().unwrap().id() as i32),
-            Signal::SIGTERM,
-        )
-        .map_err(|e| {
+        let pid = self
+            .pid()
+            .ok_or(CommandError::ServerNotRunning(self.data.id))?;
+        signal::kill(Pid::from_raw(pid as i32), Signal::SIGTERM).map_err(|e| {
             self.data.status = Status::Crashed;
             match e {
                 nix::Error::Sys(c) => CommandError::SystemError(c as i32),
@@ -64,6 +479,8 @@ impl Server {
             }
         })?;
         self.data.status = Status::Stopped;
+        self.events.push((Event::Stopped, None));
+        self.clear_process();
 
         Ok(CommandResponse::UpdateServer(
             self.data.id,
@@ -93,13 +510,38 @@ impl Server {
     pub fn stop(&mut self) -> CommandResult {
         use winapi::um::winuser::EnumWindows;
 
-        let pid = self.process.take().unwrap().id();
+        let pid = self
+            .pid()
+            .ok_or(CommandError::ServerNotRunning(self.data.id))?;
 
         unsafe {
             EnumWindows(Some(Self::find_window), (&pid as *const u32) as isize);
         }
 
         self.data.status = Status::Stopped;
+        self.events.push((Event::Stopped, None));
+        self.clear_process();
+
+        Ok(CommandResponse::UpdateServer(
+            self.data.id,
+            self.data.clone(),
+        ))
+    }
+
+    // used by the watchdog, a hung server won't stop by itself, `update_status` starts it again
+    pub fn kill_and_restart(&mut self) -> CommandResult {
+        if let Some(mut c) = self.process.take() {
+            if let Err(e) = c.kill() {
+                warn!("failed to kill {}: {}", self.data.name, e);
+            }
+            let _ = c.wait();
+        }
+
+        self.crashes += 1;
+        self.data.status = Status::Crashed;
+        self.events.push((Event::Crashed, None));
+        self.clear_process();
+        self.restart_pending = true;
 
         Ok(CommandResponse::UpdateServer(
             self.data.id,
@@ -107,24 +549,171 @@ impl Server {
         ))
     }
 
+    // the old process has to exit first so the world is never open twice, `update_status` starts
+    // the server again once it has
+    pub fn restart(&mut self) -> CommandResult {
+        let res = if self.is_running() {
+            self.stop()?
+        } else {
+            CommandResponse::UpdateServer(self.data.id, self.data.clone())
+        };
+
+        self.restart_pending = true;
+        Ok(res)
+    }
+
+    // gives up on a restart that couldn't start the server
+    pub fn cancel_restart(&mut self) {
+        self.restart_pending = false;
+    }
+
+    // whether the server is waiting to be started again and nothing is in the way
+    pub fn restart_due(&self) -> bool {
+        self.restart_pending && !self.starting && self.exiting.is_none() && !self.is_running()
+    }
+
+    fn reap(&mut self) {
+        if let Some(Ok(Some(_))) | Some(Err(_)) = self.exiting.as_mut().map(|c| c.try_wait()) {
+            self.exiting = None;
+        }
+    }
+
     pub fn update_status(&mut self) -> bool {
-        if self.data.status == Status::Open {
+        self.reap();
+        let changed = self.read_console();
+
+        if self.is_running() {
             if let Some(code) = self
                 .process
                 .as_mut()
                 .and_then(|c| c.try_wait().ok())
                 .flatten()
             {
-                self.data.status = if code.success() {
-                    Status::Stopped
+                let (status, event) = if self.eula_failed {
+                    warn!(
+                        "{} stopped because the eula hasn't been accepted",
+                        self.data.name
+                    );
+                    self.data.eula_accepted = false;
+                    (Status::Stopped, Event::Stopped)
+                } else if code.success() {
+                    (Status::Stopped, Event::Stopped)
                 } else {
-                    Status::Crashed
+                    self.crashes += 1;
+                    (Status::Crashed, Event::Crashed)
                 };
-                self.process.take();
+                self.data.status = status;
+                self.events.push((event, None));
+                self.clear_process();
 
                 return true;
             }
         }
-        false
+        changed | self.check_idle()
+    }
+
+    // players are counted from the console and the last ping, whichever sees more
+    fn check_idle(&mut self) -> bool {
+        let limit = match self.data.config.idle_shutdown_after {
+            Some(limit) if self.data.status == Status::Open => limit,
+            _ => {
+                self.idle_since = None;
+                return false;
+            }
+        };
+
+        let online = self.data.player_count > 0
+            || matches!(&self.data.info, Some(info) if info.online_players > 0);
+        // a backup or countdown would be cut short
+        if online || self.backing_up || self.data.pending_shutdown.is_some() {
+            self.idle_since = None;
+            return false;
+        }
+
+        let since = *self.idle_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < Duration::from_secs(limit) {
+            return false;
+        }
+
+        info!(
+            "stopping {}, nobody has been online for {}s",
+            self.data.name, limit
+        );
+        match self.stop() {
+            Ok(_) => {
+                self.data.stop_reason = Some(StopReason::Idle);
+                true
+            }
+            Err(e) => {
+                warn!("failed to stop {}: {}", self.data.name, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use barista::config::ServerConfig;
+
+    fn idle_server(limit: u64) -> Server {
+        let config = format!(
+            "name: test\ndir: /tmp\njar: server.jar\nargs: []\nidle_shutdown_after: {}",
+            limit
+        );
+        let config: ServerConfig = serde_yaml::from_str(&config).unwrap();
+        let mut server = Server::new(ServerData::new(0, config));
+        server.data.status = Status::Open;
+        server
+    }
+
+    fn idle_for(secs: u64) -> Option<Instant> {
+        Instant::now().checked_sub(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn pings_keep_idle_timer() {
+        let mut server = idle_server(60);
+        server.idle_since = idle_for(30);
+        let since = server.idle_since;
+
+        assert!(server.update_health(Ok(ServerInfo::default())));
+        assert!(!server.check_idle());
+        assert_eq!(server.idle_since, since);
+    }
+
+    #[test]
+    fn players_reset_idle_timer() {
+        let mut server = idle_server(60);
+        server.idle_since = idle_for(30);
+
+        let info = ServerInfo {
+            online_players: 1,
+            ..ServerInfo::default()
+        };
+        server.update_health(Ok(info));
+        assert!(!server.check_idle());
+        assert_eq!(server.idle_since, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn empty_server_answering_pings_is_stopped() {
+        let mut server = idle_server(60);
+        server.process = Some(process::Command::new("sleep").arg("60").spawn().unwrap());
+        server.idle_since = idle_for(120);
+
+        server.update_health(Ok(ServerInfo::default()));
+        let stopped = server.check_idle();
+        // reaped before anything's checked, so a failed assert doesn't leave it behind
+        if let Some(mut child) = server.process.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        assert!(stopped);
+        assert_eq!(server.data.stop_reason, Some(StopReason::Idle));
+        assert_eq!(server.data.status, Status::Stopped);
     }
 }
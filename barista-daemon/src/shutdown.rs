@@ -0,0 +1,195 @@
+use super::start;
+use super::{broadcast, dispatch_events, GlobalState, ServerContext};
+use barista::command::*;
+use barista::server::PendingShutdown;
+use chrono::Utc;
+use log::{info, warn};
+use std::time::{Duration, Instant};
+use tokio::time::delay_until;
+
+fn describe(secs: u64) -> String {
+    let (n, unit) = match (secs % 3600, secs % 60) {
+        (0, _) if secs >= 3600 => (secs / 3600, "hour"),
+        (_, 0) if secs >= 60 => (secs / 60, "minute"),
+        _ => (secs, "second"),
+    };
+
+    if n == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", n, unit)
+    }
+}
+
+fn announce(server: &mut super::Server, msg: &str) {
+    let cmd = if server.data.config.shutdown.tellraw {
+        let text = serde_json::json!({ "text": msg, "color": "yellow" });
+        format!("tellraw @a {}", text)
+    } else {
+        format!("say {}", msg)
+    };
+
+    if let Err(e) = server.send_console(&cmd) {
+        warn!("failed to warn players: {}", e);
+    }
+}
+
+fn warning(restart: bool, secs: u64) -> String {
+    let action = if restart { "restart" } else { "stop" };
+    format!("The server will {} in {}", action, describe(secs))
+}
+
+// warns players if the countdown is still the one that was started, returns false once it isn't
+fn warn_players(state: &GlobalState, id: usize, token: u64, msg: &str) -> bool {
+    let mut lock = match state.write() {
+        Ok(lock) => lock,
+        Err(_) => return false,
+    };
+    let server = &mut lock.servers[id];
+    if server.shutdown_token != token || server.data.pending_shutdown.is_none() {
+        return false;
+    }
+
+    announce(server, msg);
+    true
+}
+
+fn finish(state: &GlobalState, id: usize, token: u64, restart: bool) {
+    let mut guard = match state.write() {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+    let lock = &mut *guard;
+    let server = &mut lock.servers[id];
+    if server.shutdown_token != token || server.data.pending_shutdown.is_none() {
+        return;
+    }
+    let _ctx = ServerContext::enter(&server.data);
+
+    server.data.pending_shutdown = None;
+    let res = if restart {
+        server.restart()
+    } else {
+        server.stop()
+    };
+    dispatch_events(&lock.webhooks, server);
+
+    match res {
+        Ok(res) => broadcast(&lock.tx, &res),
+        Err(e) => {
+            warn!("failed to stop {}: {}", server.data.name, e);
+            let data = server.data.clone();
+            broadcast(&lock.tx, &CommandResponse::UpdateServer(data.id, data));
+        }
+    }
+}
+
+async fn countdown(state: GlobalState, id: usize, token: u64, delay: u64, restart: bool) {
+    let start = Instant::now();
+    let mut warnings = state.read().unwrap().servers[id]
+        .data
+        .config
+        .shutdown
+        .warnings
+        .clone();
+    warnings.sort_unstable_by(|a, b| b.cmp(a));
+
+    if !warn_players(&state, id, token, &warning(restart, delay)) {
+        return;
+    }
+
+    for secs in warnings.into_iter().filter(|w| *w < delay && *w > 0) {
+        delay_until((start + Duration::from_secs(delay - secs)).into()).await;
+        if !warn_players(&state, id, token, &warning(restart, secs)) {
+            return;
+        }
+    }
+
+    delay_until((start + Duration::from_secs(delay)).into()).await;
+    finish(&state, id, token, restart);
+}
+
+// stops or restarts the server straight away without a delay, otherwise counts down to it
+pub async fn request(
+    state: GlobalState,
+    id: usize,
+    delay: Option<u64>,
+    restart: bool,
+) -> CommandResult {
+    let stopped = state
+        .read()?
+        .servers
+        .get(id)
+        .ok_or(CommandError::NonExistentServer(id))?
+        .is_stopped();
+    // restarting a stopped server starts it straight away
+    if restart && stopped {
+        return start::start(&state, id).await;
+    }
+
+    begin(state, id, delay, restart)
+}
+
+fn begin(state: GlobalState, id: usize, delay: Option<u64>, restart: bool) -> CommandResult {
+    let task_state = state.clone();
+    let mut lock = state.write()?;
+    let server = lock
+        .servers
+        .get_mut(id)
+        .ok_or(CommandError::NonExistentServer(id))?;
+    let _ctx = ServerContext::enter(&server.data);
+
+    let delay = match delay {
+        Some(delay) if delay > 0 && server.is_running() => delay,
+        _ if restart => return server.restart(),
+        _ => return server.stop(),
+    };
+
+    // replaces any countdown that's already running
+    server.shutdown_token += 1;
+    server.data.pending_shutdown = Some(PendingShutdown {
+        at: Utc::now().timestamp() + delay as i64,
+        restart,
+    });
+    info!(
+        "{} will {} in {}",
+        server.data.name,
+        if restart { "restart" } else { "stop" },
+        describe(delay)
+    );
+
+    tokio::spawn(countdown(
+        task_state,
+        id,
+        server.shutdown_token,
+        delay,
+        restart,
+    ));
+    Ok(CommandResponse::UpdateServer(id, server.data.clone()))
+}
+
+pub fn cancel(state: &GlobalState, id: usize) -> CommandResult {
+    let mut lock = state.write()?;
+    let server = lock
+        .servers
+        .get_mut(id)
+        .ok_or(CommandError::NonExistentServer(id))?;
+    let _ctx = ServerContext::enter(&server.data);
+
+    let pending = server
+        .data
+        .pending_shutdown
+        .take()
+        .ok_or(CommandError::NoPendingShutdown(id))?;
+    server.shutdown_token += 1;
+
+    let action = if pending.restart {
+        "restart"
+    } else {
+        "shutdown"
+    };
+    info!("cancelled the {} of {}", action, server.data.name);
+    announce(server, &format!("The {} has been cancelled", action));
+
+    Ok(CommandResponse::UpdateServer(id, server.data.clone()))
+}
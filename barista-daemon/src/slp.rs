@@ -0,0 +1,296 @@
+use barista::server::ServerInfo;
+use serde_json::Value;
+use std::io;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::prelude::*;
+use tokio::time::timeout;
+
+static PING_TIMEOUT: Duration = Duration::from_secs(3);
+static MAX_PACKET_LEN: i32 = 2 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum SlpError {
+    IoError(io::Error),
+    InvalidResponse(String),
+    Timeout,
+}
+
+impl From<io::Error> for SlpError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl From<serde_json::Error> for SlpError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::InvalidResponse(e.to_string())
+    }
+}
+
+impl std::fmt::Display for SlpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Self::IoError(e) => format!("io error: {}", e),
+            Self::InvalidResponse(e) => format!("invalid status response: {}", e),
+            Self::Timeout => "timed out".to_string(),
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+pub fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return buf.push(byte);
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+pub fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_varint(buf, value.len() as i32);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+// prefixes a packet's id and data with its length
+pub fn packet(id: i32, data: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+    write_varint(&mut body, id);
+    body.extend_from_slice(data);
+
+    let mut buf = vec![];
+    write_varint(&mut buf, body.len() as i32);
+    buf.extend(body);
+    buf
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub async fn read_varint<R: AsyncRead + Unpin>(rd: &mut R) -> io::Result<i32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let byte = rd.read_u8().await?;
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(invalid_data("varint too long"))
+}
+
+// reads a whole packet, returning its id and the rest of its data
+pub async fn read_packet<R: AsyncRead + Unpin>(rd: &mut R) -> io::Result<(i32, Vec<u8>)> {
+    let len = read_varint(rd).await?;
+    if len <= 0 || len > MAX_PACKET_LEN {
+        return Err(invalid_data("invalid packet length"));
+    }
+
+    let mut buf = vec![0; len as usize];
+    rd.read_exact(&mut buf).await?;
+
+    let mut reader = Reader::new(&buf);
+    let id = reader.varint()?;
+    Ok((id, buf[reader.pos..].to_vec()))
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn varint(&mut self) -> io::Result<i32> {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let byte = *self
+                .buf
+                .get(self.pos)
+                .ok_or_else(|| invalid_data("unexpected end of packet"))?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u32) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value as i32);
+            }
+        }
+        Err(invalid_data("varint too long"))
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + 2)
+            .ok_or_else(|| invalid_data("unexpected end of packet"))?;
+        self.pos += 2;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        let len = self.varint()?;
+        if len < 0 || self.pos + len as usize > self.buf.len() {
+            return Err(invalid_data("invalid string length"));
+        }
+
+        let s = &self.buf[self.pos..self.pos + len as usize];
+        self.pos += len as usize;
+        String::from_utf8(s.to_vec()).map_err(|_| invalid_data("string isn't utf8"))
+    }
+}
+
+// flattens a chat component into plain text
+fn chat_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Object(obj) => {
+            let mut text = obj.get("text").map(chat_text).unwrap_or_default();
+            if let Some(Value::Array(extra)) = obj.get("extra") {
+                extra.iter().for_each(|e| text.push_str(&chat_text(e)));
+            }
+            text
+        }
+        Value::Array(parts) => parts.iter().map(chat_text).collect(),
+        _ => String::new(),
+    }
+}
+
+fn parse_status(json: &str) -> Result<ServerInfo, SlpError> {
+    let status: Value = serde_json::from_str(json)?;
+    let missing = |field: &str| SlpError::InvalidResponse(format!("missing {}", field));
+
+    let version = status.get("version").ok_or_else(|| missing("version"))?;
+    let players = status.get("players").ok_or_else(|| missing("players"))?;
+
+    Ok(ServerInfo {
+        motd: status.get("description").map(chat_text).unwrap_or_default(),
+        version: version["name"].as_str().unwrap_or_default().to_string(),
+        protocol: version["protocol"].as_i64().unwrap_or(-1) as i32,
+        max_players: players["max"].as_u64().unwrap_or(0),
+        online_players: players["online"].as_u64().unwrap_or(0),
+    })
+}
+
+async fn status(host: &str, port: u16) -> Result<ServerInfo, SlpError> {
+    let mut stream = TcpStream::connect((host, port)).await?;
+
+    let mut handshake = vec![];
+    write_varint(&mut handshake, -1);
+    write_string(&mut handshake, host);
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_varint(&mut handshake, 1);
+
+    stream.write_all(&packet(0x00, &handshake)).await?;
+    stream.write_all(&packet(0x00, &[])).await?;
+
+    let (id, data) = read_packet(&mut stream).await?;
+    if id != 0x00 {
+        return Err(SlpError::InvalidResponse(format!(
+            "unexpected packet id {}",
+            id
+        )));
+    }
+
+    parse_status(&Reader::new(&data).string()?)
+}
+
+pub async fn ping(host: &str, port: u16) -> Result<ServerInfo, SlpError> {
+    timeout(PING_TIMEOUT, status(host, port))
+        .await
+        .unwrap_or(Err(SlpError::Timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    // answers a single status request the way a server would
+    async fn respond(response: Vec<u8>) -> u16 {
+        let mut listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let (id, handshake) = read_packet(&mut stream).await.unwrap();
+            assert_eq!(id, 0x00);
+            let mut reader = Reader::new(&handshake);
+            reader.varint().unwrap();
+            assert_eq!(reader.string().unwrap(), "127.0.0.1");
+            assert_eq!(reader.u16().unwrap(), port);
+            assert_eq!(reader.varint().unwrap(), 1);
+
+            assert_eq!(read_packet(&mut stream).await.unwrap(), (0x00, vec![]));
+            stream.write_all(&response).await.unwrap();
+        });
+
+        port
+    }
+
+    fn status_packet(json: &str) -> Vec<u8> {
+        let mut data = vec![];
+        write_string(&mut data, json);
+        packet(0x00, &data)
+    }
+
+    #[tokio::test]
+    async fn pings_server() {
+        let json = r#"{
+            "version": {"name": "1.16.5", "protocol": 754},
+            "players": {"max": 20, "online": 3},
+            "description": {"text": "a ", "extra": [{"text": "minecraft"}, " server"]}
+        }"#;
+        let port = respond(status_packet(json)).await;
+
+        let info = ping("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            info,
+            ServerInfo {
+                motd: "a minecraft server".to_string(),
+                version: "1.16.5".to_string(),
+                protocol: 754,
+                max_players: 20,
+                online_players: 3,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_fields() {
+        let port = respond(status_packet(r#"{"version": {"name": "1.16.5"}}"#)).await;
+
+        match ping("127.0.0.1", port).await {
+            Err(SlpError::InvalidResponse(e)) => assert_eq!(e, "missing players"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_packets() {
+        let port = respond(packet(0x01, &[0; 8])).await;
+
+        match ping("127.0.0.1", port).await {
+            Err(SlpError::InvalidResponse(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn varints_round_trip() {
+        for value in &[0, 1, 127, 128, 25565, i32::MAX, -1] {
+            let mut buf = vec![];
+            write_varint(&mut buf, *value);
+            assert_eq!(Reader::new(&buf).varint().unwrap(), *value);
+            assert_eq!(read_varint(&mut buf.as_slice()).await.unwrap(), *value);
+        }
+    }
+}
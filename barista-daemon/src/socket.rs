@@ -0,0 +1,180 @@
+use super::{respond, serialize_ws, GlobalState, ServerError};
+use barista::command::Command;
+use barista::socket::MAX_MESSAGE_LEN;
+use log::{error, info, trace, warn};
+use nix::unistd::{getegid, geteuid};
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::net::unix::UCred;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+use tokio::prelude::*;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use warp::ws::Message;
+
+async fn read_frame<R: AsyncRead + Unpin>(rd: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let len = match rd.read_u32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message too long: {} bytes", len),
+        ));
+    }
+
+    let mut buf = vec![0; len as usize];
+    rd.read_exact(&mut buf).await?;
+    Ok(Some(buf))
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(wr: &mut W, bytes: &[u8]) -> io::Result<()> {
+    wr.write_u32(bytes.len() as u32).await?;
+    wr.write_all(bytes).await
+}
+
+async fn reply(
+    cmd: Command,
+    state: GlobalState,
+    tx: UnboundedSender<Result<Message, warp::Error>>,
+) {
+    match serialize_ws(&respond(cmd, state).await) {
+        // the client is gone if this fails, reading from it will notice
+        Ok(msg) => {
+            let _ = tx.send(Ok(msg));
+        }
+        Err(e) => error!("socket error: {}", e),
+    }
+}
+
+// the peer's credentials only carry its primary group, so the rest are looked up by its name
+fn groups(cred: &UCred) -> Vec<u32> {
+    #[cfg(not(any(target_os = "ios", target_os = "macos")))]
+    {
+        use nix::unistd::{getgrouplist, Gid, Uid, User};
+        use std::ffi::CString;
+
+        let name = match User::from_uid(Uid::from_raw(cred.uid)) {
+            Ok(Some(user)) => CString::new(user.name).ok(),
+            _ => None,
+        };
+        if let Some(list) = name.and_then(|n| getgrouplist(&n, Gid::from_raw(cred.gid)).ok()) {
+            // the primary group is included in the list
+            return list.into_iter().map(|g| g.as_raw()).collect();
+        }
+    }
+
+    vec![cred.gid]
+}
+
+// the same clients the socket's permissions let in, root, the daemon's user and anyone in its
+// group
+fn allows(euid: u32, egid: u32, uid: u32, groups: &[u32]) -> bool {
+    uid == 0 || uid == euid || groups.contains(&egid)
+}
+
+fn is_allowed(cred: &UCred) -> bool {
+    allows(
+        geteuid().as_raw(),
+        getegid().as_raw(),
+        cred.uid,
+        &groups(cred),
+    )
+}
+
+async fn handle_client(stream: UnixStream, state: GlobalState) {
+    match stream.peer_cred() {
+        Ok(cred) if is_allowed(&cred) => trace!("socket client connected: {:?}", cred),
+        Ok(cred) => {
+            return warn!(
+                "rejecting socket client uid {} gid {}: permission denied",
+                cred.uid, cred.gid
+            );
+        }
+        Err(e) => return error!("failed to get socket peer credentials: {}", e),
+    }
+
+    let (mut rd, mut wr) = stream.into_split();
+    let (tx, mut rx) = unbounded_channel::<Result<Message, warp::Error>>();
+
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = rx.recv().await {
+            if let Err(e) = write_frame(&mut wr, msg.as_bytes()).await {
+                return trace!("failed to send socket message to client: {}", e);
+            }
+        }
+    });
+
+    {
+        let mut lock = state.write().unwrap();
+        lock.socket_clients.push(tx.clone());
+    }
+
+    loop {
+        let bytes = match read_frame(&mut rd).await {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return trace!("socket client disconnected"),
+            Err(e) => return error!("error reading socket message: {}", e),
+        };
+
+        let cmd = match serde_cbor::from_slice::<Command>(&bytes) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                error!("socket error: {}", e);
+                continue;
+            }
+        };
+
+        // the client matches tagged responses up itself, so a slow command doesn't hold up the
+        // ones sent after it
+        match cmd {
+            Command::Tagged(_, _) => {
+                tokio::spawn(reply(cmd, state.clone(), tx.clone()));
+            }
+            cmd => reply(cmd, state.clone(), tx.clone()).await,
+        }
+    }
+}
+
+pub async fn listen(path: &Path, state: GlobalState) -> Result<(), ServerError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    let mut listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o660))?;
+    info!("listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(handle_client(stream, state.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_the_daemons_user_and_group() {
+        // the daemon runs as 1000:1000
+        assert!(allows(1000, 1000, 0, &[0]));
+        assert!(allows(1000, 1000, 1000, &[1000]));
+        assert!(allows(1000, 1000, 1001, &[1000]));
+        // only a supplementary group matches
+        assert!(allows(1000, 1000, 1001, &[1001, 27, 1000]));
+
+        assert!(!allows(1000, 1000, 1001, &[1001]));
+        assert!(!allows(1000, 1000, 1001, &[1001, 27]));
+        assert!(!allows(1000, 1000, 1001, &[]));
+    }
+}
@@ -0,0 +1,87 @@
+use super::ports;
+use super::preflight::{Report, Target};
+use super::{broadcast, dispatch_events, GlobalState, ServerContext};
+use barista::command::*;
+use barista::preflight::PreflightError;
+use log::{info, warn};
+
+// the checks block on the disk and on running java, so they're run without holding the lock
+async fn check(target: Target) -> Result<Report, CommandError> {
+    tokio::task::spawn_blocking(move || target.check())
+        .await
+        .map_err(|_| CommandError::UnknownSystemError)
+}
+
+// every start goes through here, the port is checked against the other servers under the same
+// lock the server is started with, so two of them can't both take it
+pub async fn start(state: &GlobalState, id: usize) -> CommandResult {
+    let target = {
+        let mut lock = state.write()?;
+        let server = lock
+            .servers
+            .get_mut(id)
+            .ok_or(CommandError::NonExistentServer(id))?;
+        if server.starting || server.is_running() {
+            return Err(CommandError::ServerRunning(id));
+        }
+        server.starting = true;
+        Target::new(server)
+    };
+
+    let report = check(target).await;
+
+    let mut guard = state.write()?;
+    let lock = &mut *guard;
+    lock.servers[id].starting = false;
+    let report = report?;
+    ports::check(&lock.servers, id)?;
+
+    let server = &mut lock.servers[id];
+    let _ctx = ServerContext::enter(&server.data);
+    let res = server.start(report);
+    dispatch_events(&lock.webhooks, server);
+    res
+}
+
+// for restarts nobody is waiting on, the server is left stopped if it can't start
+pub async fn restart(state: GlobalState, id: usize) {
+    let res = start(&state, id).await;
+
+    let mut guard = match state.write() {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+    let lock = &mut *guard;
+    let server = &mut lock.servers[id];
+    let _ctx = ServerContext::enter(&server.data);
+
+    match res {
+        Ok(res) => {
+            info!("restarted {}", server.data.name);
+            broadcast(&lock.tx, &res);
+        }
+        Err(e) => {
+            warn!("failed to start {} again: {}", server.data.name, e);
+            server.cancel_restart();
+            let data = server.data.clone();
+            broadcast(&lock.tx, &CommandResponse::UpdateServer(data.id, data));
+        }
+    }
+}
+
+pub async fn preflight(state: &GlobalState, id: usize) -> CommandResult {
+    let target = {
+        let lock = state.read()?;
+        let server = lock
+            .servers
+            .get(id)
+            .ok_or(CommandError::NonExistentServer(id))?;
+        Target::new(server)
+    };
+
+    let mut errors = check(target).await?.errors;
+    if let Some((port, name)) = ports::conflict(&state.read()?.servers, id) {
+        errors.push(PreflightError::PortInUse(port, Some(name)));
+    }
+    Ok(CommandResponse::Preflight(id, errors))
+}
@@ -0,0 +1,295 @@
+use super::access;
+use super::GlobalState;
+use barista::access::{AccessEntry, AccessList, SyncDrift};
+use barista::command::CommandError;
+use barista::config::{ServerConfig, SyncGroupConfig};
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::delay_for;
+
+static SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct SyncGroup {
+    name: String,
+    source: usize,
+    members: Vec<usize>,
+    lists: Vec<AccessList>,
+}
+
+impl SyncGroup {
+    // groups that refer to servers that don't exist are skipped
+    pub fn resolve(config: &SyncGroupConfig, servers: &[ServerConfig]) -> Option<Self> {
+        let find = |name: &str| {
+            let id = servers.iter().position(|s| s.name == name);
+            if id.is_none() {
+                warn!(
+                    "sync group {} refers to unknown server {}",
+                    config.name, name
+                );
+            }
+            id
+        };
+
+        let source = find(&config.source)?;
+        let members = config
+            .members
+            .iter()
+            .filter_map(|m| find(m))
+            .filter(|id| *id != source)
+            .collect();
+
+        Some(Self {
+            name: config.name.clone(),
+            source,
+            members,
+            lists: config.lists.clone(),
+        })
+    }
+}
+
+fn same(a: &AccessEntry, b: &AccessEntry) -> bool {
+    a.name.eq_ignore_ascii_case(&b.name)
+}
+
+// entries the source has that the member doesn't, and the other way around
+fn diff(source: &[AccessEntry], entries: &[AccessEntry]) -> (Vec<AccessEntry>, Vec<AccessEntry>) {
+    let missing = source
+        .iter()
+        .filter(|e| !entries.iter().any(|m| same(e, m)))
+        .cloned()
+        .collect();
+    let extra = entries
+        .iter()
+        .filter(|m| !source.iter().any(|e| same(e, m)))
+        .cloned()
+        .collect();
+
+    (missing, extra)
+}
+
+fn compare(
+    group: &SyncGroup,
+    dirs: &[PathBuf],
+    member: usize,
+    list: AccessList,
+) -> Result<SyncDrift, CommandError> {
+    let source = access::entries(&dirs[group.source], list)?;
+    let entries = access::entries(&dirs[member], list)?;
+    let (missing, extra) = diff(&source, &entries);
+
+    Ok(SyncDrift {
+        group: group.name.clone(),
+        server: member,
+        list,
+        missing,
+        extra,
+    })
+}
+
+// only members that differ from their source are returned
+pub fn drift(state: &GlobalState) -> Result<Vec<SyncDrift>, CommandError> {
+    let (groups, dirs) = {
+        let lock = state.read()?;
+        let dirs: Vec<_> = lock
+            .servers
+            .iter()
+            .map(|s| Path::new(&s.data.config.dir).to_path_buf())
+            .collect();
+        (lock.sync_groups.clone(), dirs)
+    };
+
+    let mut drift = vec![];
+    for group in groups.iter() {
+        for member in group.members.iter() {
+            for list in group.lists.iter() {
+                let d = compare(group, &dirs, *member, *list)?;
+                if !d.missing.is_empty() || !d.extra.is_empty() {
+                    drift.push(d);
+                }
+            }
+        }
+    }
+
+    Ok(drift)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    server: usize,
+    list: AccessList,
+    name: String,
+    add: bool,
+}
+
+// a running member applies changes on its own time and might never write them back to its
+// file, so what's been sent is remembered instead of sending it again every tick
+#[derive(Debug, Default)]
+struct Pushed {
+    sent: Vec<Change>,
+    warned: Vec<Change>,
+}
+
+impl Pushed {
+    // returns the changes that haven't been sent yet, anything that's stopped drifting is
+    // forgotten so it's sent again if it drifts later
+    fn pending<'a>(
+        &mut self,
+        drift: &'a [SyncDrift],
+    ) -> Vec<(Change, &'a SyncDrift, &'a AccessEntry)> {
+        let mut needed = vec![];
+        let mut pending = vec![];
+
+        for d in drift.iter() {
+            let changes = d
+                .missing
+                .iter()
+                .map(|e| (e, true))
+                .chain(d.extra.iter().map(|e| (e, false)));
+
+            for (entry, add) in changes {
+                let change = Change {
+                    server: d.server,
+                    list: d.list,
+                    name: entry.name.to_lowercase(),
+                    add,
+                };
+
+                if !self.sent.contains(&change) {
+                    pending.push((change.clone(), d, entry));
+                } else if !self.warned.contains(&change) {
+                    let action = if add { "adding" } else { "removing" };
+                    warn!(
+                        "server {} hasn't applied {} {} to its {} for group {}, not sending it again",
+                        d.server, action, entry.name, d.list, d.group
+                    );
+                    self.warned.push(change.clone());
+                }
+                needed.push(change);
+            }
+        }
+
+        self.sent.retain(|c| needed.contains(c));
+        self.warned.retain(|c| needed.contains(c));
+        pending
+    }
+}
+
+pub async fn run(state: GlobalState) {
+    let mut pushed = Pushed::default();
+
+    loop {
+        match drift(&state) {
+            Ok(drift) => {
+                for d in drift.iter() {
+                    info!(
+                        "server {} has drifted from the {} of group {}, {} missing and {} extra",
+                        d.server,
+                        d.list,
+                        d.group,
+                        d.missing.len(),
+                        d.extra.len()
+                    );
+                }
+
+                for (change, d, entry) in pushed.pending(&drift) {
+                    match access::sync_entry(&state, d.server, d.list, entry, change.add) {
+                        Ok(_) => pushed.sent.push(change),
+                        Err(e) => error!(
+                            "failed to sync {} to server {} for group {}: {}",
+                            entry.name, d.server, d.group, e
+                        ),
+                    }
+                }
+            }
+            Err(e) => error!("failed to check sync groups: {}", e),
+        }
+
+        delay_for(SYNC_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str) -> AccessEntry {
+        AccessEntry {
+            uuid: None,
+            name: name.to_string(),
+        }
+    }
+
+    fn drift(missing: &[&str], extra: &[&str]) -> SyncDrift {
+        SyncDrift {
+            group: "main".to_string(),
+            server: 1,
+            list: AccessList::Whitelist,
+            missing: missing.iter().map(|n| entry(n)).collect(),
+            extra: extra.iter().map(|n| entry(n)).collect(),
+        }
+    }
+
+    fn names(pending: &[(Change, &SyncDrift, &AccessEntry)]) -> Vec<(String, bool)> {
+        pending
+            .iter()
+            .map(|(c, _, e)| (e.name.clone(), c.add))
+            .collect()
+    }
+
+    #[test]
+    fn diffs_entries_ignoring_case() {
+        let source = vec![entry("Steve"), entry("Alex"), entry("Notch")];
+        let member = vec![entry("steve"), entry("Herobrine")];
+
+        let (missing, extra) = diff(&source, &member);
+        assert_eq!(missing, vec![entry("Alex"), entry("Notch")]);
+        assert_eq!(extra, vec![entry("Herobrine")]);
+
+        let (missing, extra) = diff(&source, &source);
+        assert!(missing.is_empty());
+        assert!(extra.is_empty());
+    }
+
+    #[test]
+    fn changes_are_only_sent_once() {
+        let mut pushed = Pushed::default();
+
+        let first = [drift(&["Alex"], &["Herobrine"])];
+        let pending = pushed.pending(&first);
+        assert_eq!(
+            names(&pending),
+            vec![("Alex".to_string(), true), ("Herobrine".to_string(), false)]
+        );
+        pushed.sent.extend(pending.into_iter().map(|(c, _, _)| c));
+
+        // the running server hasn't written either change back yet
+        let second = [drift(&["alex", "Notch"], &["Herobrine"])];
+        assert_eq!(
+            names(&pushed.pending(&second)),
+            vec![("Notch".to_string(), true)]
+        );
+        assert_eq!(pushed.warned.len(), 2);
+    }
+
+    #[test]
+    fn resolved_changes_are_forgotten() {
+        let mut pushed = Pushed::default();
+
+        let drifted = [drift(&["Alex"], &[])];
+        let pending = pushed.pending(&drifted);
+        pushed.sent.extend(pending.into_iter().map(|(c, _, _)| c));
+        assert!(pushed.pending(&drifted).is_empty());
+
+        assert!(pushed.pending(&[]).is_empty());
+        assert!(pushed.sent.is_empty());
+        assert!(pushed.warned.is_empty());
+
+        // drifting again later sends it again
+        assert_eq!(
+            names(&pushed.pending(&drifted)),
+            vec![("Alex".to_string(), true)]
+        );
+    }
+}
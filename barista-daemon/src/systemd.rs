@@ -0,0 +1,178 @@
+use barista::server::ServerData;
+use std::cell::RefCell;
+
+thread_local! {
+    static CONTEXT: RefCell<Option<(usize, String)>> = RefCell::default();
+}
+
+// while this is alive, anything logged from the current thread is tagged with the server's
+// SERVER_ID and SERVER_NAME in the journal
+pub struct ServerContext {
+    prev: Option<(usize, String)>,
+}
+
+impl ServerContext {
+    pub fn enter(server: &ServerData) -> Self {
+        let prev = CONTEXT.with(|c| c.replace(Some((server.id, server.name.clone()))));
+        Self { prev }
+    }
+}
+
+impl Drop for ServerContext {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CONTEXT.with(|c| *c.borrow_mut() = prev);
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) {
+    use log::warn;
+    use nix::sys::socket::{self, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType, UnixAddr};
+    use nix::unistd::close;
+    use std::env;
+
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+
+    let addr = match path.strip_prefix('@') {
+        Some(name) => UnixAddr::new_abstract(name.as_bytes()),
+        None => UnixAddr::new(path.as_str()),
+    };
+
+    let res = addr.and_then(|addr| {
+        let fd = socket::socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        let res = socket::sendto(
+            fd,
+            state.as_bytes(),
+            &SockAddr::Unix(addr),
+            MsgFlags::empty(),
+        );
+        close(fd)?;
+        res
+    });
+
+    if let Err(e) = res {
+        warn!("failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) {}
+
+#[cfg(target_os = "linux")]
+pub fn watchdog_enabled() -> bool {
+    use std::env;
+
+    if env::var("WATCHDOG_USEC").is_err() {
+        return false;
+    }
+
+    match env::var("WATCHDOG_PID").map(|p| p.parse::<u32>()) {
+        Ok(Ok(pid)) => pid == std::process::id(),
+        _ => true,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watchdog_enabled() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+mod journal {
+    use super::CONTEXT;
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use std::os::unix::net::UnixDatagram;
+
+    static JOURNAL_PATH: &str = "/run/systemd/journal/socket";
+
+    fn add_field(buf: &mut Vec<u8>, key: &str, value: &str) {
+        buf.extend_from_slice(key.as_bytes());
+        // values with newlines have to use the length prefixed form
+        if value.contains('\n') {
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buf.push(b'=');
+        }
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(b'\n');
+    }
+
+    fn priority(level: Level) -> &'static str {
+        match level {
+            Level::Error => "3",
+            Level::Warn => "4",
+            Level::Info => "6",
+            Level::Debug | Level::Trace => "7",
+        }
+    }
+
+    pub struct JournalLogger {
+        socket: UnixDatagram,
+        level: LevelFilter,
+    }
+
+    impl JournalLogger {
+        pub fn init(level: LevelFilter) -> Result<(), Box<dyn std::error::Error>> {
+            let logger = Self {
+                socket: UnixDatagram::unbound()?,
+                level,
+            };
+
+            log::set_boxed_logger(Box::new(logger))?;
+            log::set_max_level(level);
+            Ok(())
+        }
+    }
+
+    impl Log for JournalLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+
+            let mut buf = vec![];
+            add_field(&mut buf, "MESSAGE", &record.args().to_string());
+            add_field(&mut buf, "PRIORITY", priority(record.level()));
+            add_field(&mut buf, "SYSLOG_IDENTIFIER", "mined");
+            add_field(&mut buf, "TARGET", record.target());
+
+            if let Some(file) = record.file() {
+                add_field(&mut buf, "CODE_FILE", file);
+            }
+
+            if let Some(line) = record.line() {
+                add_field(&mut buf, "CODE_LINE", &line.to_string());
+            }
+
+            CONTEXT.with(|c| {
+                if let Some((id, name)) = c.borrow().as_ref() {
+                    add_field(&mut buf, "SERVER_ID", &id.to_string());
+                    add_field(&mut buf, "SERVER_NAME", name);
+                }
+            });
+
+            if let Err(e) = self.socket.send_to(&buf, JOURNAL_PATH) {
+                eprintln!("failed to log to journald: {}", e);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use journal::JournalLogger;
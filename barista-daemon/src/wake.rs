@@ -0,0 +1,258 @@
+use super::properties::Properties;
+use super::slp::{self, Reader};
+use super::start;
+use super::{broadcast, GlobalState, ServerContext};
+use barista::config::WakeConfig;
+use log::{debug, info, warn};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::*;
+use tokio::time::{delay_for, timeout};
+
+static CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// how long the server can be started by something else before the port is given back, java takes
+// longer than this to bind it
+static POLL_INTERVAL: Duration = Duration::from_millis(500);
+static CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the address to listen on, only while the server is stopped and has wake on connect set
+fn sleeping(state: &GlobalState, id: usize) -> Option<((String, u16), WakeConfig)> {
+    let lock = state.read().ok()?;
+    let server = &lock.servers[id];
+    let cfg = server.data.config.wake_on_connect.clone()?;
+    if !server.is_stopped() {
+        return None;
+    }
+
+    let address = match Properties::load(Path::new(&server.data.config.dir)) {
+        Ok(props) => props.bind_address(),
+        Err(_) => ("0.0.0.0".to_string(), 25565),
+    };
+    Some((address, cfg))
+}
+
+fn status_json(protocol: i32, cfg: &WakeConfig) -> String {
+    serde_json::json!({
+        "version": { "name": "Sleeping", "protocol": protocol },
+        "players": { "max": 0, "online": 0 },
+        "description": { "text": cfg.motd },
+    })
+    .to_string()
+}
+
+// answers a status ping or turns away a login, returning the player's name for a login
+async fn handle(stream: &mut TcpStream, cfg: &WakeConfig) -> io::Result<Option<String>> {
+    let (id, data) = slp::read_packet(stream).await?;
+    if id != 0x00 {
+        return Ok(None);
+    }
+
+    let mut handshake = Reader::new(&data);
+    let protocol = handshake.varint()?;
+    handshake.string()?;
+    handshake.u16()?;
+
+    match handshake.varint()? {
+        1 => {
+            let (id, _) = slp::read_packet(stream).await?;
+            if id != 0x00 {
+                return Ok(None);
+            }
+
+            let mut res = vec![];
+            slp::write_string(&mut res, &status_json(protocol, cfg));
+            stream.write_all(&slp::packet(0x00, &res)).await?;
+
+            // the client measures the latency with a ping that's sent back as it is
+            if let Ok((0x01, payload)) = slp::read_packet(stream).await {
+                stream.write_all(&slp::packet(0x01, &payload)).await?;
+            }
+            Ok(None)
+        }
+        2 => {
+            let name = match slp::read_packet(stream).await {
+                Ok((0x00, data)) => Reader::new(&data).string().unwrap_or_default(),
+                _ => String::new(),
+            };
+
+            let reason = serde_json::json!({ "text": cfg.message }).to_string();
+            let mut res = vec![];
+            slp::write_string(&mut res, &reason);
+            stream.write_all(&slp::packet(0x00, &res)).await?;
+            Ok(Some(name))
+        }
+        _ => Ok(None),
+    }
+}
+
+// returns once someone tries to join, or false if the server was started some other way
+async fn listen(
+    state: &GlobalState,
+    id: usize,
+    mut listener: TcpListener,
+    cfg: &WakeConfig,
+) -> bool {
+    loop {
+        let accepted = timeout(POLL_INTERVAL, listener.accept()).await;
+        if sleeping(state, id).is_none() {
+            return false;
+        }
+
+        let (mut stream, addr) = match accepted {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => {
+                warn!("failed to accept a connection: {}", e);
+                continue;
+            }
+            Err(_) => continue,
+        };
+
+        match timeout(CLIENT_TIMEOUT, handle(&mut stream, cfg)).await {
+            Ok(Ok(Some(name))) => {
+                info!("{} ({}) tried to join, starting the server", name, addr);
+                return true;
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => debug!("bad connection from {}: {}", addr, e),
+            Err(_) => debug!("connection from {} timed out", addr),
+        }
+    }
+}
+
+// the server can start on its own port while it's held here, the listener gives it up right away
+fn set_sleeping(state: &GlobalState, id: usize, sleeping: bool) {
+    if let Ok(mut lock) = state.write() {
+        lock.servers[id].sleeping = sleeping;
+    }
+}
+
+async fn wake(state: &GlobalState, id: usize) {
+    let res = start::start(state, id).await;
+
+    let lock = match state.read() {
+        Ok(lock) => lock,
+        Err(_) => return,
+    };
+    let server = &lock.servers[id];
+    let _ctx = ServerContext::enter(&server.data);
+
+    match res {
+        Ok(res) => broadcast(&lock.tx, &res),
+        Err(e) => warn!("failed to start {}: {}", server.data.name, e),
+    }
+}
+
+pub async fn run(state: GlobalState, id: usize) {
+    // only warned about once until it works again, the port might be taken for a while
+    let mut bind_failed = false;
+
+    loop {
+        delay_for(CHECK_INTERVAL).await;
+
+        let ((host, port), cfg) = match sleeping(&state, id) {
+            Some(target) => target,
+            None => continue,
+        };
+
+        let listener = match TcpListener::bind((host.as_str(), port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                if !bind_failed {
+                    warn!("failed to listen on {}:{}: {}", host, port, e);
+                }
+                bind_failed = true;
+                continue;
+            }
+        };
+        bind_failed = false;
+        debug!("listening on {}:{} until someone joins", host, port);
+
+        set_sleeping(&state, id, true);
+        let woken = listen(&state, id, listener, &cfg).await;
+        set_sleeping(&state, id, false);
+        if woken {
+            wake(&state, id).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WakeConfig {
+        serde_yaml::from_str("{motd: zzz, message: starting up}").unwrap()
+    }
+
+    fn handshake(next_state: i32) -> Vec<u8> {
+        let mut data = vec![];
+        slp::write_varint(&mut data, 754);
+        slp::write_string(&mut data, "localhost");
+        data.extend_from_slice(&25565u16.to_be_bytes());
+        slp::write_varint(&mut data, next_state);
+        slp::packet(0x00, &data)
+    }
+
+    // runs `handle` on the first connection while the client sends `packets` and reads replies
+    async fn connect(
+        packets: Vec<Vec<u8>>,
+        replies: usize,
+    ) -> (Option<String>, Vec<(i32, Vec<u8>)>) {
+        let mut listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            for packet in packets {
+                stream.write_all(&packet).await.unwrap();
+            }
+
+            let mut res = vec![];
+            for _ in 0..replies {
+                res.push(slp::read_packet(&mut stream).await.unwrap());
+            }
+            res
+        });
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let name = handle(&mut stream, &config()).await.unwrap();
+        (name, client.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_status_requests() {
+        let packets = vec![
+            handshake(1),
+            slp::packet(0x00, &[]),
+            slp::packet(0x01, &42u64.to_be_bytes()),
+        ];
+        let (name, replies) = connect(packets, 2).await;
+        assert_eq!(name, None);
+
+        let (id, data) = &replies[0];
+        assert_eq!(*id, 0x00);
+        let status: serde_json::Value =
+            serde_json::from_str(&Reader::new(data).string().unwrap()).unwrap();
+        assert_eq!(status["description"]["text"], "zzz");
+        assert_eq!(status["version"]["protocol"], 754);
+
+        assert_eq!(replies[1], (0x01, 42u64.to_be_bytes().to_vec()));
+    }
+
+    #[tokio::test]
+    async fn turns_away_logins() {
+        let mut login = vec![];
+        slp::write_string(&mut login, "Steve");
+        let packets = vec![handshake(2), slp::packet(0x00, &login)];
+        let (name, replies) = connect(packets, 1).await;
+        assert_eq!(name.as_deref(), Some("Steve"));
+
+        let (id, data) = &replies[0];
+        assert_eq!(*id, 0x00);
+        let reason: serde_json::Value =
+            serde_json::from_str(&Reader::new(data).string().unwrap()).unwrap();
+        assert_eq!(reason["text"], "starting up");
+    }
+}
@@ -0,0 +1,67 @@
+use log::{info, warn};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+static JSTACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn jstack(pid: u32, dir: &Path) -> Result<(), String> {
+    let output = timeout(
+        JSTACK_TIMEOUT,
+        Command::new("jstack")
+            .arg("-l")
+            .arg(pid.to_string())
+            .output(),
+    )
+    .await
+    .map_err(|_| "jstack timed out".to_string())?
+    .map_err(|e| format!("failed to run jstack: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "jstack failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let dumps = dir.join("thread-dumps");
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = dumps.join(format!("{}.txt", secs));
+
+    fs::create_dir_all(&dumps)
+        .await
+        .map_err(|e| format!("failed to create {}: {}", dumps.display(), e))?;
+    fs::write(&path, output.stdout)
+        .await
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+
+    info!("saved thread dump to {}", path.display());
+    Ok(())
+}
+
+// the jvm prints a thread dump to stdout on SIGQUIT, which ends up in the console log
+#[cfg(unix)]
+fn sigquit(pid: u32) {
+    use nix::sys::signal::{self, Signal};
+    use nix::unistd::Pid;
+
+    match signal::kill(Pid::from_raw(pid as i32), Signal::SIGQUIT) {
+        Ok(_) => info!("sent SIGQUIT, the thread dump is in the console output"),
+        Err(e) => warn!("failed to send SIGQUIT: {}", e),
+    }
+}
+
+#[cfg(windows)]
+fn sigquit(_pid: u32) {}
+
+pub async fn thread_dump(pid: u32, dir: &Path) {
+    if let Err(e) = jstack(pid, dir).await {
+        warn!("failed to save a thread dump: {}", e);
+        sigquit(pid);
+    }
+}
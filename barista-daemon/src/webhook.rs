@@ -0,0 +1,234 @@
+use barista::config::{Event, WebhookConfig};
+use barista::server::ServerData;
+use log::{error, trace, warn};
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::delay_for;
+
+#[derive(Debug)]
+pub struct Webhooks {
+    hooks: Vec<WebhookConfig>,
+    client: reqwest::Client,
+}
+
+fn message(event: Event, server: &str, player: Option<&str>) -> String {
+    let player = player.unwrap_or("someone");
+    match event {
+        Event::Started => format!("{} started", server),
+        Event::Stopped => format!("{} stopped", server),
+        Event::Crashed => format!("{} crashed", server),
+        Event::PlayerJoin => format!("{} joined {}", player, server),
+        Event::PlayerLeave => format!("{} left {}", player, server),
+        Event::BackupFinished => format!("backup of {} finished", server),
+        Event::BackupFailed => format!("backup of {} failed", server),
+        Event::Unresponsive => format!("{} stopped responding", server),
+    }
+}
+
+fn escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn render(hook: &WebhookConfig, event: Event, server: &ServerData, player: Option<&str>) -> String {
+    let msg = message(event, &server.name, player);
+
+    match &hook.body {
+        Some(body) => body
+            .replace("{{event}}", &escape(&event.to_string()))
+            .replace("{{server}}", &escape(&server.name))
+            .replace("{{id}}", &server.id.to_string())
+            .replace("{{player}}", &escape(player.unwrap_or("")))
+            .replace("{{message}}", &escape(&msg)),
+        None => json!({
+            "event": event,
+            "server": server.name,
+            "id": server.id,
+            "player": player,
+            "message": msg,
+        })
+        .to_string(),
+    }
+}
+
+async fn deliver(client: reqwest::Client, url: String, body: String, retries: u32) {
+    let mut delay = Duration::from_secs(1);
+
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            delay_for(delay).await;
+            delay *= 2;
+        }
+
+        let res = client
+            .post(&url)
+            .header("content-type", "application/json")
+            .body(body.clone())
+            .send()
+            .await;
+
+        match res {
+            Ok(r) if r.status().is_success() => {
+                return trace!("delivered webhook to {}", url);
+            }
+            Ok(r) => warn!("webhook {} responded with {}", url, r.status()),
+            Err(e) => warn!("failed to deliver webhook to {}: {}", url, e),
+        }
+    }
+
+    error!("giving up on webhook {} after {} retries", url, retries);
+}
+
+impl Webhooks {
+    pub fn new(hooks: Vec<WebhookConfig>) -> Self {
+        Self {
+            hooks,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn dispatch(&self, event: Event, server: &ServerData, player: Option<&str>) {
+        for hook in self.hooks.iter() {
+            if !hook.events.is_empty() && !hook.events.contains(&event) {
+                continue;
+            }
+
+            let body = render(hook, event, server, player);
+            tokio::spawn(deliver(
+                self.client.clone(),
+                hook.url.clone(),
+                body,
+                hook.retries,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use barista::config::ServerConfig;
+    use serde_json::Value;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::prelude::*;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+    use tokio::time::timeout;
+
+    fn server(name: &str) -> ServerData {
+        let config = format!("name: {}\ndir: /tmp\njar: server.jar\nargs: []", name);
+        let config: ServerConfig = serde_yaml::from_str(&config).unwrap();
+        ServerData::new(3, config)
+    }
+
+    fn hook(yaml: &str) -> WebhookConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    async fn read_body(stream: &mut TcpStream) -> String {
+        let mut buf = vec![];
+        let mut chunk = [0; 1024];
+        let end = loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed before the headers ended");
+            buf.extend_from_slice(&chunk[..n]);
+            if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+        };
+
+        let headers = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+        let len: usize = headers
+            .lines()
+            .find_map(|l| l.strip_prefix("content-length:"))
+            .map(|l| l.trim().parse().unwrap())
+            .unwrap_or(0);
+
+        let mut body = buf[end..].to_vec();
+        body.resize(len, 0);
+        let read = buf.len() - end;
+        stream.read_exact(&mut body[read..]).await.unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    // answers a request with each status in turn, sending on the bodies it was given
+    async fn serve(statuses: Vec<u16>, tx: UnboundedSender<String>) -> String {
+        let mut listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tx.send(read_body(&mut stream).await).unwrap();
+
+                let res = format!(
+                    "HTTP/1.1 {} Test\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(res.as_bytes()).await.unwrap();
+            }
+        });
+
+        format!("http://127.0.0.1:{}/hook", port)
+    }
+
+    async fn next(rx: &mut UnboundedReceiver<String>) -> Option<String> {
+        timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[test]
+    fn renders_default_body() {
+        let hook = hook("url: http://localhost");
+        let body = render(&hook, Event::PlayerJoin, &server("survival"), Some("Steve"));
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "event": "player_join",
+                "server": "survival",
+                "id": 3,
+                "player": "Steve",
+                "message": "Steve joined survival",
+            })
+        );
+    }
+
+    #[test]
+    fn renders_escaped_template() {
+        let hook =
+            hook(r#"{url: "http://localhost", body: '{"content": "{{message}} ({{id}})"}'}"#);
+        let body = render(&hook, Event::Crashed, &server(r#"the "main" one"#), None);
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["content"], r#"the "main" one crashed (3)"#);
+    }
+
+    #[tokio::test]
+    async fn dispatches_subscribed_events() {
+        let (tx, mut rx) = unbounded_channel();
+        let url = serve(vec![200], tx).await;
+        let hooks = Webhooks::new(vec![hook(&format!("{{url: {}, events: [started]}}", url))]);
+
+        hooks.dispatch(Event::Crashed, &server("survival"), None);
+        hooks.dispatch(Event::Started, &server("survival"), None);
+
+        let body: Value = serde_json::from_str(&next(&mut rx).await.unwrap()).unwrap();
+        assert_eq!(body["event"], "started");
+        assert_eq!(next(&mut rx).await, None);
+    }
+
+    #[tokio::test]
+    async fn retries_failed_deliveries() {
+        let (tx, mut rx) = unbounded_channel();
+        let url = serve(vec![500, 200], tx).await;
+
+        deliver(reqwest::Client::new(), url, "{}".to_string(), 2).await;
+
+        assert_eq!(next(&mut rx).await.as_deref(), Some("{}"));
+        assert_eq!(next(&mut rx).await.as_deref(), Some("{}"));
+        assert_eq!(next(&mut rx).await, None);
+    }
+}
@@ -1,6 +1,8 @@
+use barista::backup::BackupInfo;
 use barista::command::*;
 use barista::server::ServerData;
 use log::{error, trace};
+use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{MessageEvent, WebSocket};
@@ -23,6 +25,7 @@ type AppLink = (&'static str, AppRoute);
 struct App {
     link: ComponentLink<Self>,
     server_list: Vec<ServerData>,
+    backups: HashMap<usize, Vec<BackupInfo>>,
     ws: WebSocket,
     nav_items: Vec<AppLink>,
 }
@@ -85,6 +88,7 @@ impl Component for App {
         let s = Self {
             link,
             server_list: vec![],
+            backups: HashMap::new(),
             ws: WebSocket::new("ws://localhost:3000/cmd").unwrap(),
             nav_items: vec![("Servers", AppRoute::Index)],
         };
@@ -101,7 +105,21 @@ impl Component for App {
             Msg::Websocket(res) => match res {
                 CommandResponse::UpdateServers(servers) => self.server_list = servers,
                 CommandResponse::UpdateServer(id, server) => self.server_list[id] = server,
+                CommandResponse::Backups(id, backups) => {
+                    self.backups.insert(id, backups);
+                }
                 CommandResponse::Error(e) => error!("{}", e),
+                CommandResponse::RconResponse(_, _)
+                | CommandResponse::Properties(_, _)
+                | CommandResponse::AccessList(_, _, _)
+                | CommandResponse::SyncDrift(_)
+                | CommandResponse::Backup(_, _)
+                | CommandResponse::BackupVerification(_, _)
+                | CommandResponse::Schedules(_, _)
+                | CommandResponse::Preflight(_, _)
+                | CommandResponse::JavaRuntimes(_)
+                | CommandResponse::BackupProgress(_, _)
+                | CommandResponse::Tagged(_, _) => return false,
             },
         }
 
@@ -110,10 +128,11 @@ impl Component for App {
 
     fn view(&self) -> Html {
         let s = self.server_list.clone();
+        let b = self.backups.clone();
         let ws = self.ws.clone();
         let routes = Router::<AppRoute, ()>::render(move |sw| match sw {
             AppRoute::Index => html! {
-                <ServerPage servers={s.clone()} ws={ws.clone()}/>
+                <ServerPage servers={s.clone()} backups={b.clone()} ws={ws.clone()}/>
             },
         });
         html! {
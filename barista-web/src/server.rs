@@ -1,6 +1,9 @@
+use barista::backup::BackupInfo;
 use barista::command::*;
 use barista::server::{ServerData, Status};
 use log::error;
+use std::collections::HashMap;
+use wasm_bindgen::JsValue;
 use web_sys::WebSocket;
 use yew::prelude::*;
 
@@ -16,6 +19,7 @@ macro_rules! neq_assign {
 #[derive(Properties, Clone)]
 pub struct Props {
     pub servers: Vec<ServerData>,
+    pub backups: HashMap<usize, Vec<BackupInfo>>,
     pub ws: WebSocket,
 }
 
@@ -26,6 +30,7 @@ pub enum Msg {
 
 pub struct ServerPage {
     servers: Vec<ServerData>,
+    backups: HashMap<usize, Vec<BackupInfo>>,
     ws: WebSocket,
     link: ComponentLink<Self>,
 }
@@ -42,7 +47,7 @@ impl ServerPage {
 
     fn handle_button(server: &ServerData) -> Msg {
         let cmd = match server.status {
-            Status::Open => Command::StopServer(server.id),
+            Status::Open | Status::Unresponsive => Command::StopServer(server.id, None),
             Status::Stopped => Command::StartServer(server.id),
             _ => return Msg::None,
         };
@@ -50,9 +55,90 @@ impl ServerPage {
         Msg::SendWebsocket(cmd)
     }
 
+    fn format_info(server: &ServerData) -> Html {
+        if server.status == Status::Open && !server.responding {
+            return html! {
+                <span class="server-info server-unresponsive">{ "Not responding" }</span>
+            };
+        }
+
+        match &server.info {
+            Some(info) => html! {
+                <span class="server-info">{
+                    format!("{} ({}/{}) - {}", info.version, info.online_players, info.max_players, info.motd)
+                }</span>
+            },
+            None => html! {},
+        }
+    }
+
+    fn format_shutdown(&self, server: &ServerData) -> Html {
+        let pending = match server.pending_shutdown {
+            Some(pending) => pending,
+            None => return html! {},
+        };
+        let action = if pending.restart { "Restarting" } else { "Stopping" };
+        let at = js_sys::Date::new(&(pending.at as f64 * 1000.0).into());
+        let at = String::from(at.to_locale_time_string("default"));
+        let cmd = Command::CancelShutdown(server.id);
+
+        html! {
+            <>
+                <span class="server-info">{ format!("{} at {}", action, at) }</span>
+                <button class="server-btn" onclick=self.link.callback(move |_| Msg::SendWebsocket(cmd.clone()))>{
+                    "Cancel"
+                }</button>
+            </>
+        }
+    }
+
+    fn format_backups(&self, server: &ServerData) -> Html {
+        let backups = match self.backups.get(&server.id) {
+            Some(backups) => backups,
+            None => return html! {},
+        };
+        let stopped = server.status == Status::Stopped || server.status == Status::Crashed;
+        let id = server.id;
+
+        html! {
+            <div class="server-backups">
+                { for backups.iter().map(|b| {
+                    let cmd = Command::RestoreBackup(id, b.name.clone());
+                    let created = js_sys::Date::new(&(b.created as f64 * 1000.0).into());
+                    let created = String::from(created.to_locale_string("default", &JsValue::UNDEFINED));
+                    html! {
+                        <div class="server-backup">
+                            <span>{ format!("{} ({} MB, {})", b.name, b.size / 1024 / 1024, created) }</span>
+                            <button class="server-btn" disabled=!stopped onclick=self.link.callback(move |_| Msg::SendWebsocket(cmd.clone()))>{
+                                "Restore"
+                            }</button>
+                        </div>
+                    }
+                }) }
+            </div>
+        }
+    }
+
+    fn format_version(server: &ServerData) -> Html {
+        let mut parts = vec![];
+        if let Some(version) = &server.minecraft_version {
+            parts.push(format!("Minecraft {}", version));
+        }
+        if let Some(java) = server.required_java {
+            parts.push(format!("Java {}+", java));
+        }
+
+        if parts.is_empty() {
+            html! {}
+        } else {
+            html! { <span class="server-info">{ parts.join(", ") }</span> }
+        }
+    }
+
     fn format_server(&self, server: &ServerData) -> Html {
         let server = server.clone();
         let s = server.clone();
+        let id = server.id;
         html! {
             <div class="server">
                 <span class="server-name">{
@@ -62,11 +148,39 @@ impl ServerPage {
                     format!("Player Count: {}", server.player_count)
                 }</span>
                 <span class="server-status">{
-                    format!("Status: {}", server.status)
+                    match server.stop_reason {
+                        Some(reason) => format!("Status: {} ({})", server.status, reason),
+                        None => format!("Status: {}", server.status),
+                    }
                 }</span>
+                { Self::format_info(&server) }
+                { Self::format_version(&server) }
+                { if server.restart_required {
+                    html! { <span class="server-info">{ "Restart required" }</span> }
+                } else {
+                    html! {}
+                } }
+                { self.format_shutdown(&server) }
                 <button class="server-btn" onclick=self.link.callback(move |_| Self::handle_button(&s))>{
                     server.status
                 }</button>
+                <button class="server-btn" onclick=self.link.callback(move |_| Msg::SendWebsocket(Command::Backup(id)))>{
+                    "Backup"
+                }</button>
+                <button class="server-btn" onclick=self.link.callback(move |_| Msg::SendWebsocket(Command::ListBackups(id)))>{
+                    "Backups"
+                }</button>
+                { self.format_backups(&server) }
+                { if !server.eula_accepted {
+                    let cmd = Command::AcceptEula(server.id);
+                    html! {
+                        <button class="server-btn" onclick=self.link.callback(move |_| Msg::SendWebsocket(cmd.clone()))>{
+                            "Accept EULA"
+                        }</button>
+                    }
+                } else {
+                    html! {}
+                } }
             </div>
         }
     }
@@ -79,13 +193,14 @@ impl Component for ServerPage {
     fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
         Self {
             servers: props.servers,
+            backups: props.backups,
             ws: props.ws,
             link,
         }
     }
 
     fn change(&mut self, props: Self::Properties) -> ShouldRender {
-        neq_assign!((self, props) => ws, servers)
+        neq_assign!((self, props) => ws, servers, backups)
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
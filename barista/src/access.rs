@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessList {
+    Whitelist,
+    Ops,
+    BannedPlayers,
+    BannedIps,
+}
+
+impl AccessList {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::Whitelist => "whitelist.json",
+            Self::Ops => "ops.json",
+            Self::BannedPlayers => "banned-players.json",
+            Self::BannedIps => "banned-ips.json",
+        }
+    }
+}
+
+impl std::fmt::Display for AccessList {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Self::Whitelist => "whitelist",
+            Self::Ops => "ops",
+            Self::BannedPlayers => "banned players",
+            Self::BannedIps => "banned ips",
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+// for banned ips the name is the address and there's no uuid
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccessEntry {
+    pub uuid: Option<String>,
+    pub name: String,
+}
+
+// how a member of a sync group differs from the group's source
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyncDrift {
+    pub group: String,
+    pub server: usize,
+    pub list: AccessList,
+    // entries the source has but the member doesn't
+    pub missing: Vec<AccessEntry>,
+    // entries the member has but the source doesn't
+    pub extra: Vec<AccessEntry>,
+}
@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackupInfo {
+    // the file name inside the server's backup directory, or of the snapshot in its repository
+    pub name: String,
+    pub size: u64,
+    // unix timestamp
+    pub created: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum BackupProgress {
+    Saving,
+    Archiving,
+    Finished(BackupInfo),
+    Failed(String),
+}
+
+// problems found checking a backup's checksum or a snapshot's chunks, empty if it's intact
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackupVerification {
+    pub name: String,
+    pub problems: Vec<String>,
+}
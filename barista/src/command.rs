@@ -1,19 +1,114 @@
+use super::access::{AccessEntry, AccessList, SyncDrift};
+use super::backup::{BackupInfo, BackupProgress, BackupVerification};
+use super::java::JavaRuntime;
+use super::preflight::PreflightError;
+use super::schedule::ScheduleInfo;
 use super::server::ServerData;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::PoisonError;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Command {
     GetServers,
     StartServer(usize),
-    StopServer(usize),
+    // with a delay in seconds, players are warned and it can be cancelled until then
+    StopServer(usize, Option<u64>),
+    // runs a console command over rcon, answered with a `CommandResponse::RconResponse`
+    Rcon(usize, String),
+    GetProperties(usize),
+    // only the given keys are changed, if the server is running it has to be restarted for them
+    // to apply
+    SetProperties(usize, BTreeMap<String, String>),
+    // writes `eula=true` to the server's eula.txt
+    AcceptEula(usize),
+    GetAccessList(usize, AccessList),
+    // takes a player name, or an address for `AccessList::BannedIps`. while the server is running
+    // the change goes through its console, otherwise the json file is edited directly
+    AddAccessEntry(usize, AccessList, String),
+    RemoveAccessEntry(usize, AccessList, String),
+    // compares every sync group member with its source, without changing anything
+    GetSyncDrift,
+    // saves the world and archives it, progress is pushed to every client as
+    // `CommandResponse::BackupProgress`
+    Backup(usize),
+    ListBackups(usize),
+    // takes the name of the backup
+    DeleteBackup(usize, String),
+    // replaces the server's files with the ones in a backup, only while it's stopped
+    RestoreBackup(usize, String),
+    // checks every backup's checksum and every chunk a snapshot uses
+    VerifyBackups(usize),
+    // stops the server and starts it again once it has exited, the delay works like `StopServer`
+    RestartServer(usize, Option<u64>),
+    CancelShutdown(usize),
+    ListSchedules(usize),
+    // these take the name of the schedule and respond with every schedule of the server
+    RunScheduleNow(usize, String),
+    EnableSchedule(usize, String),
+    DisableSchedule(usize, String),
+    // checks everything a server needs to start, without starting it
+    Preflight(usize),
+    // looks for java installations again as well
+    ListJavaRuntimes,
+    // the response to a tagged command is wrapped in a `CommandResponse::Tagged` with the same
+    // tag, so it can be told apart from the updates that are pushed to every client
+    Tagged(u64, Box<Command>),
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::GetServers => "GetServers",
+            Self::StartServer(_) => "StartServer",
+            Self::StopServer(_, _) => "StopServer",
+            Self::Rcon(_, _) => "Rcon",
+            Self::GetProperties(_) => "GetProperties",
+            Self::SetProperties(_, _) => "SetProperties",
+            Self::AcceptEula(_) => "AcceptEula",
+            Self::GetAccessList(_, _) => "GetAccessList",
+            Self::AddAccessEntry(_, _, _) => "AddAccessEntry",
+            Self::RemoveAccessEntry(_, _, _) => "RemoveAccessEntry",
+            Self::GetSyncDrift => "GetSyncDrift",
+            Self::Backup(_) => "Backup",
+            Self::ListBackups(_) => "ListBackups",
+            Self::DeleteBackup(_, _) => "DeleteBackup",
+            Self::RestoreBackup(_, _) => "RestoreBackup",
+            Self::VerifyBackups(_) => "VerifyBackups",
+            Self::RestartServer(_, _) => "RestartServer",
+            Self::CancelShutdown(_) => "CancelShutdown",
+            Self::ListSchedules(_) => "ListSchedules",
+            Self::RunScheduleNow(_, _) => "RunScheduleNow",
+            Self::EnableSchedule(_, _) => "EnableSchedule",
+            Self::DisableSchedule(_, _) => "DisableSchedule",
+            Self::Preflight(_) => "Preflight",
+            Self::ListJavaRuntimes => "ListJavaRuntimes",
+            Self::Tagged(_, cmd) => cmd.name(),
+        }
+    }
 }
 
+// responses are short lived and mostly serialized straight away, so boxing isn't worth it
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum CommandResponse {
     UpdateServers(Vec<ServerData>),
     UpdateServer(usize, ServerData),
+    RconResponse(usize, String),
+    Properties(usize, BTreeMap<String, String>),
+    AccessList(usize, AccessList, Vec<AccessEntry>),
+    SyncDrift(Vec<SyncDrift>),
+    Backup(usize, BackupInfo),
+    BackupProgress(usize, BackupProgress),
+    // newest first
+    Backups(usize, Vec<BackupInfo>),
+    BackupVerification(usize, Vec<BackupVerification>),
+    Schedules(usize, Vec<ScheduleInfo>),
+    // empty if nothing is wrong
+    Preflight(usize, Vec<PreflightError>),
+    JavaRuntimes(Vec<JavaRuntime>),
     Error(CommandError),
+    Tagged(u64, Box<CommandResponse>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -22,6 +117,29 @@ pub enum CommandError {
     NonExistentServer(usize),
     SystemError(i32),
     UnknownSystemError,
+    ServerNotRunning(usize),
+    RconNotEnabled(usize),
+    RconError(String),
+    InvalidProperty(String),
+    EulaNotAccepted(usize),
+    InvalidPlayer(String),
+    UnknownPlayer(String),
+    LookupFailed(String),
+    InvalidAccessList(String),
+    // the change was sent to the server but it hasn't saved it yet
+    AccessListNotUpdated(usize, AccessList),
+    BackupInProgress(usize),
+    BackupFailed(String),
+    NonExistentBackup(String),
+    ServerRunning(usize),
+    RestoreInProgress(usize),
+    RestoreFailed(String),
+    NonExistentSchedule(String),
+    ScheduleFailed(String),
+    NoPendingShutdown(usize),
+    // the port and the server using it, if it's one of ours
+    PortInUse(u16, Option<String>),
+    PreflightFailed(usize, Vec<PreflightError>),
 }
 
 pub type CommandResult = Result<CommandResponse, CommandError>;
@@ -33,6 +151,39 @@ impl std::fmt::Display for CommandError {
             Self::SystemError(e) => format!("system error: {}", e),
             Self::NonExistentServer(id) => format!("server id {} doesn't exist", id),
             Self::UnknownSystemError => "unknown system error".to_string(),
+            Self::ServerNotRunning(id) => format!("server {} isn't running", id),
+            Self::RconNotEnabled(id) => format!(
+                "rcon isn't enabled for server {}, set enable-rcon and rcon.password in server.properties",
+                id
+            ),
+            Self::RconError(e) => format!("rcon error: {}", e),
+            Self::InvalidProperty(key) => format!("{:?} isn't a valid property name", key),
+            Self::InvalidPlayer(name) => format!("{:?} isn't a valid player name or address", name),
+            Self::UnknownPlayer(name) => format!("there's no player called {}", name),
+            Self::LookupFailed(e) => format!("failed to look up player: {}", e),
+            Self::InvalidAccessList(e) => format!("failed to parse access list: {}", e),
+            Self::AccessListNotUpdated(id, list) => format!(
+                "server {} hasn't saved the change to its {} yet, check it again later",
+                id, list
+            ),
+            Self::BackupInProgress(id) => format!("server {} is already being backed up", id),
+            Self::BackupFailed(e) => format!("backup failed: {}", e),
+            Self::NonExistentBackup(name) => format!("backup {} doesn't exist", name),
+            Self::ServerRunning(id) => format!("server {} has to be stopped first", id),
+            Self::RestoreInProgress(id) => format!("server {} is being restored", id),
+            Self::RestoreFailed(e) => format!("restore failed: {}", e),
+            Self::NonExistentSchedule(name) => format!("schedule {} doesn't exist", name),
+            Self::ScheduleFailed(e) => format!("schedule failed: {}", e),
+            Self::NoPendingShutdown(id) => format!("server {} isn't about to stop", id),
+            Self::PortInUse(port, Some(name)) => format!("port {} is already used by {}", port, name),
+            Self::PortInUse(port, None) => format!("port {} is already in use", port),
+            Self::PreflightFailed(id, errors) => {
+                let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+                format!("server {} can't start: {}", id, errors.join(", "))
+            }
+            Self::EulaNotAccepted(id) => {
+                format!("the eula hasn't been accepted for server {}", id)
+            }
         };
 
         write!(f, "{}", msg)
@@ -45,6 +196,15 @@ impl<T> From<PoisonError<T>> for CommandError {
     }
 }
 
+impl From<std::io::Error> for CommandError {
+    fn from(e: std::io::Error) -> Self {
+        match e.raw_os_error() {
+            Some(c) => Self::SystemError(c),
+            None => Self::UnknownSystemError,
+        }
+    }
+}
+
 #[cfg(unix)]
 impl From<nix::Error> for CommandError {
     fn from(e: nix::Error) -> Self {
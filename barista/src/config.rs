@@ -1,3 +1,4 @@
+use super::access::AccessList;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -6,10 +7,291 @@ pub struct ServerConfig {
     pub dir: String,
     pub jar: String,
     pub args: Vec<String>,
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    // seconds without any players online before the server is stopped
+    #[serde(default)]
+    pub idle_shutdown_after: Option<u64>,
+    // listens on the server's port while it's stopped and starts it when someone tries to join
+    #[serde(default)]
+    pub wake_on_connect: Option<WakeConfig>,
+    // players connecting to the router with one of these are sent to this server, `*.` matches
+    // any subdomain
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    // the java binary to use, or the major version it needs like `8`, or `17+` for that or newer,
+    // otherwise the oldest installed one the jar can run on, or the first java on PATH
+    #[serde(default)]
+    pub java: Option<String>,
+}
+
+fn default_sleeping_motd() -> String {
+    "Sleeping, join to start the server".to_string()
+}
+
+fn default_starting_message() -> String {
+    "The server is starting, retry in a moment".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WakeConfig {
+    // shown in the server list while it's stopped
+    #[serde(default = "default_sleeping_motd")]
+    pub motd: String,
+    // the reason players are disconnected with
+    #[serde(default = "default_starting_message")]
+    pub message: String,
+}
+
+fn default_warnings() -> Vec<u64> {
+    vec![600, 300, 60, 10]
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ShutdownConfig {
+    // seconds before a delayed stop or restart when players are warned
+    #[serde(default = "default_warnings")]
+    pub warnings: Vec<u64>,
+    // warns with a coloured `tellraw` instead of `say`
+    #[serde(default)]
+    pub tellraw: bool,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            warnings: default_warnings(),
+            tellraw: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct BackupConfig {
+    // defaults to `backups` in the server's directory, which is left out of the backups
+    #[serde(default)]
+    pub dir: Option<String>,
+    // paths relative to the server's directory, the whole directory is backed up if this is empty
+    #[serde(default)]
+    pub worlds: Vec<String>,
+    // old backups are pruned after every backup, nothing is deleted without this
+    #[serde(default)]
+    pub retention: Option<RetentionConfig>,
+    #[serde(default)]
+    pub mode: BackupMode,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupMode {
+    // a compressed tarball per backup
+    #[default]
+    Archive,
+    // snapshots in a repository inside the backup directory, files are split into chunks that
+    // are only stored once so unchanged parts of the world cost nothing
+    Dedup,
+}
+
+// a backup is kept if any rule keeps it, then the oldest are removed until they fit in `max_size`.
+// the newest backup is always kept
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct RetentionConfig {
+    #[serde(default)]
+    pub keep_last: usize,
+    // the newest backup of each of the last n days, weeks and months that have one
+    #[serde(default)]
+    pub daily: usize,
+    #[serde(default)]
+    pub weekly: usize,
+    #[serde(default)]
+    pub monthly: usize,
+    // in bytes, snapshots count the size of the files in them even though chunks are shared
+    #[serde(default)]
+    pub max_size: Option<u64>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScheduleConfig {
+    pub name: String,
+    // five fields like crontab, or six with seconds first
+    pub cron: String,
+    // a tz database name like `Europe/London`, defaults to utc
+    #[serde(default)]
+    pub timezone: Option<String>,
+    pub action: ScheduleAction,
+    // seconds to count down before a stop or restart, warning players on the way
+    #[serde(default)]
+    pub delay: Option<u64>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleAction {
+    Start,
+    Stop,
+    Restart,
+    Backup,
+    // announced to every player with `say`
+    Say(String),
+    // any console command
+    Command(String),
+}
+
+impl std::fmt::Display for ScheduleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Self::Start => "start".to_string(),
+            Self::Stop => "stop".to_string(),
+            Self::Restart => "restart".to_string(),
+            Self::Backup => "backup".to_string(),
+            Self::Say(msg) => format!("say {}", msg),
+            Self::Command(cmd) => format!("command {}", cmd),
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+fn default_max_failures() -> u32 {
+    3
+}
+
+fn default_startup_grace() -> u64 {
+    120
+}
+
+fn default_thread_dump() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WatchdogConfig {
+    // how many pings in a row have to fail before the server counts as hung
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    // seconds after starting where failed pings are ignored, worlds can take a while to load
+    #[serde(default = "default_startup_grace")]
+    pub startup_grace: u64,
+    // saves a thread dump to `thread-dumps/` in the server's directory
+    #[serde(default = "default_thread_dump")]
+    pub thread_dump: bool,
+    #[serde(default)]
+    pub restart: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Started,
+    Stopped,
+    Crashed,
+    PlayerJoin,
+    PlayerLeave,
+    BackupFinished,
+    BackupFailed,
+    Unresponsive,
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Event::Started => "started",
+            Event::Stopped => "stopped",
+            Event::Crashed => "crashed",
+            Event::PlayerJoin => "player_join",
+            Event::PlayerLeave => "player_leave",
+            Event::BackupFinished => "backup_finished",
+            Event::BackupFailed => "backup_failed",
+            Event::Unresponsive => "unresponsive",
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WebhookConfig {
+    pub url: String,
+    // an empty list means every event
+    #[serde(default)]
+    pub events: Vec<Event>,
+    // a json template, `{{event}}`, `{{server}}`, `{{id}}`, `{{player}}` and `{{message}}` are
+    // replaced with their json escaped values
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_sync_lists() -> Vec<AccessList> {
+    vec![AccessList::Whitelist, AccessList::Ops]
+}
+
+// keeps the access lists of every member the same as the source server's, servers are referred to
+// by name
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SyncGroupConfig {
+    pub name: String,
+    pub source: String,
+    pub members: Vec<String>,
+    #[serde(default = "default_sync_lists")]
+    pub lists: Vec<AccessList>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Config {
     pub version: u64,
     pub servers: Vec<ServerConfig>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    #[serde(default)]
+    pub sync_groups: Vec<SyncGroupConfig>,
+    #[serde(default)]
+    pub router: Option<RouterConfig>,
+    // new servers without a server-port are given a free one from here
+    #[serde(default)]
+    pub ports: Option<PortRange>,
+    // searched for java installations as well as the usual places
+    #[serde(default)]
+    pub java_dirs: Vec<String>,
+}
+
+// both ends are included
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+fn default_router_listen() -> String {
+    "0.0.0.0:25565".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RouterConfig {
+    #[serde(default = "default_router_listen")]
+    pub listen: String,
+    // the name of the server used for hostnames that don't match, otherwise they're disconnected
+    #[serde(default)]
+    pub default: Option<String>,
+    // sends a PROXY protocol v2 header so servers see the player's address, they have to have
+    // it turned on as well
+    #[serde(default)]
+    pub proxy_protocol: bool,
 }
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+// a jdk or jre found by reading its `release` file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JavaRuntime {
+    // the java binary inside it
+    pub path: String,
+    pub version: String,
+    pub major: u32,
+    pub vendor: Option<String>,
+}
+
+// `1.8.0_275` is java 8, everything from java 9 on starts with the major version
+pub fn major_version(version: &str) -> Option<u32> {
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+
+    match parts.next()?.parse().ok()? {
+        1 => parts.next()?.parse().ok(),
+        major => Some(major),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_major_versions() {
+        assert_eq!(major_version("1.8.0_275"), Some(8));
+        assert_eq!(major_version("1.7"), Some(7));
+        assert_eq!(major_version("11.0.9"), Some(11));
+        assert_eq!(major_version("17"), Some(17));
+        assert_eq!(major_version("16-ea"), Some(16));
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        assert_eq!(major_version(""), None);
+        assert_eq!(major_version("1"), None);
+        assert_eq!(major_version("jdk-17"), None);
+    }
+}
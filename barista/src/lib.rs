@@ -1,3 +1,9 @@
+pub mod access;
+pub mod backup;
 pub mod command;
 pub mod config;
+pub mod java;
+pub mod preflight;
+pub mod schedule;
 pub mod server;
+pub mod socket;
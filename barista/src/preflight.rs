@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+// something that would stop a server from starting, paths are as they're written in the config
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PreflightError {
+    MissingDirectory(String),
+    DirectoryNotWritable(String),
+    MissingJar(String),
+    // the jar and why it isn't a valid zip
+    InvalidJar(String, String),
+    JavaNotFound(String),
+    // none of the installed runtimes meet the server's requirement
+    NoJavaRuntime(String),
+    UnknownJavaVersion(String),
+    // the version found and the one required
+    IncompatibleJava(u32, u32),
+    // bytes available and required
+    LowDiskSpace(u64, u64),
+    // the port and the server using it, if it's one of ours
+    PortInUse(u16, Option<String>),
+}
+
+impl std::fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Self::MissingDirectory(dir) => format!("{} doesn't exist", dir),
+            Self::DirectoryNotWritable(dir) => format!("{} isn't writable", dir),
+            Self::MissingJar(jar) => format!("{} doesn't exist", jar),
+            Self::InvalidJar(jar, e) => format!("{} isn't a valid jar: {}", jar, e),
+            Self::JavaNotFound(java) => format!("couldn't run {}", java),
+            Self::NoJavaRuntime(req) => format!("no installed java matches {}", req),
+            Self::UnknownJavaVersion(java) => format!("couldn't tell which version {} is", java),
+            Self::IncompatibleJava(found, required) => format!(
+                "java {} is installed, but java {} or newer is required",
+                found, required
+            ),
+            Self::LowDiskSpace(available, required) => format!(
+                "only {} MiB of disk space is free, at least {} MiB is needed",
+                available / 1024 / 1024,
+                required / 1024 / 1024
+            ),
+            Self::PortInUse(port, Some(name)) => {
+                format!("port {} is already used by {}", port, name)
+            }
+            Self::PortInUse(port, None) => format!("port {} is already in use", port),
+        };
+
+        write!(f, "{}", msg)
+    }
+}
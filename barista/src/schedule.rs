@@ -0,0 +1,22 @@
+use super::config::ScheduleAction;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduleRun {
+    // unix timestamp
+    pub time: i64,
+    // only set if it failed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduleInfo {
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub action: ScheduleAction,
+    pub enabled: bool,
+    // unix timestamp, none when disabled or the expression never matches again
+    pub next_run: Option<i64>,
+    pub last_run: Option<ScheduleRun>,
+}
@@ -8,6 +8,50 @@ pub struct ServerData {
     pub player_count: u64,
     pub status: Status,
     pub config: ServerConfig,
+    // from the last server list ping, kept while the server stops responding
+    pub info: Option<ServerInfo>,
+    pub responding: bool,
+    // server.properties changed while it was running
+    pub restart_required: bool,
+    pub eula_accepted: bool,
+    // a stop or restart that's counting down
+    pub pending_shutdown: Option<PendingShutdown>,
+    // why the daemon stopped the server by itself, cleared when it starts
+    pub stop_reason: Option<StopReason>,
+    // read from the jar, if it says
+    pub minecraft_version: Option<String>,
+    pub required_java: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Idle,
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            StopReason::Idle => "idle",
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PendingShutdown {
+    // unix timestamp
+    pub at: i64,
+    pub restart: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ServerInfo {
+    pub motd: String,
+    pub version: String,
+    pub protocol: i32,
+    pub max_players: u64,
+    pub online_players: u64,
 }
 
 impl ServerData {
@@ -18,23 +62,27 @@ impl ServerData {
             player_count: 0,
             status: Status::Stopped,
             config,
+            info: None,
+            responding: false,
+            restart_required: false,
+            eula_accepted: false,
+            pending_shutdown: None,
+            stop_reason: None,
+            minecraft_version: None,
+            required_java: None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
 pub enum Status {
     Open,
     Starting,
     Stopping,
+    #[default]
     Stopped,
     Crashed,
-}
-
-impl Default for Status {
-    fn default() -> Status {
-        Status::Stopped
-    }
+    Unresponsive,
 }
 
 impl std::fmt::Display for Status {
@@ -45,6 +93,7 @@ impl std::fmt::Display for Status {
             Status::Stopping => "Stopping",
             Status::Stopped => "Stopped",
             Status::Crashed => "Crashed",
+            Status::Unresponsive => "Unresponsive",
         };
 
         write!(f, "{}", msg)
@@ -0,0 +1,4 @@
+// the control socket speaks the same cbor protocol as the websocket, but since it's a stream
+// every message is prefixed with its length as a big endian u32
+pub static SOCKET_PATH: &str = "/run/mined/mined.sock";
+pub static MAX_MESSAGE_LEN: u32 = 16 * 1024 * 1024;